@@ -0,0 +1,1066 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use super::action_log::ActionLog;
+use super::reflink::reflink;
+use super::verify::same_content;
+use super::walk::file_id;
+use super::{
+    get_hash, Digest, DuplicateFile, DuplicateGroup, DuplicateReport, FileKey, FindDoublesError,
+    HashOptions, Operation, Pattern, ScanStats, ACTION_DELETE, ACTION_HARD_LINK, ACTION_MOVE_TO,
+    ACTION_REFLINK, ACTION_SYMLINK, DEFAULT_BUFFER_SIZE, KEEP_FIRST_ROOT, KEEP_NEWEST, KEEP_OLDEST,
+    KEEP_SHORTEST_PATH,
+};
+
+/// What is done with the duplicates of each group, the copy kept being left untouched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Delete,
+    /// Replaces the duplicates by hard links to the copy kept, which must be on the same device.
+    HardLink,
+    /// Makes the duplicates share the data blocks of the copy kept, see [`reflink`].
+    Reflink,
+    /// Replaces the duplicates by symlinks to the copy kept, relative ones unless
+    /// [`ActionOptions::absolute_links`] is set.
+    Symlink,
+    /// Moves the duplicates to a quarantine directory, see [`ActionOptions::quarantine`], from
+    /// which they can be restored.
+    MoveTo,
+}
+
+/// Quarantine directory of [`Action::MoveTo`] when none is given.
+pub const DEFAULT_QUARANTINE: &str = "find_doubles_quarantine";
+
+/// Name of the manifest of the files moved, in the quarantine directory.
+pub const QUARANTINE_MANIFEST: &str = "find_doubles_manifest.tsv";
+
+/// Log of [`Action::Symlink`] when none is given, so the replacements can always be undone.
+pub const DEFAULT_SYMLINK_LOG: &str = "find_doubles_symlinks.tsv";
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Delete => ACTION_DELETE,
+            Action::HardLink => ACTION_HARD_LINK,
+            Action::Reflink => ACTION_REFLINK,
+            Action::Symlink => ACTION_SYMLINK,
+            Action::MoveTo => ACTION_MOVE_TO,
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let action = match s.to_lowercase().as_ref() {
+            ACTION_DELETE => Action::Delete,
+            ACTION_HARD_LINK => Action::HardLink,
+            ACTION_REFLINK => Action::Reflink,
+            ACTION_SYMLINK => Action::Symlink,
+            ACTION_MOVE_TO => Action::MoveTo,
+            _ => {
+                return Err(format!(
+                    "Could not parse `{}` as action, please use `{}`, `{}`, `{}`, `{}`, or `{}`.",
+                    s,
+                    ACTION_DELETE,
+                    ACTION_HARD_LINK,
+                    ACTION_REFLINK,
+                    ACTION_SYMLINK,
+                    ACTION_MOVE_TO
+                ));
+            }
+        };
+
+        Ok(action)
+    }
+}
+
+/// How the copy kept in each group of duplicates is chosen. Ties are broken by taking the first
+/// path in alphabetical order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeepRule {
+    /// The file found in the first root given.
+    #[default]
+    FirstRoot,
+    /// The file modified the longest ago.
+    Oldest,
+    /// The file modified the most recently.
+    Newest,
+    /// The file with the shortest path.
+    ShortestPath,
+}
+
+impl FromStr for KeepRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rule = match s.to_lowercase().as_ref() {
+            KEEP_FIRST_ROOT => KeepRule::FirstRoot,
+            KEEP_OLDEST => KeepRule::Oldest,
+            KEEP_NEWEST => KeepRule::Newest,
+            KEEP_SHORTEST_PATH => KeepRule::ShortestPath,
+            _ => {
+                return Err(format!(
+                    "Could not parse `{}` as keep rule, please use `{}`, `{}`, `{}`, or `{}`.",
+                    s, KEEP_FIRST_ROOT, KEEP_OLDEST, KEEP_NEWEST, KEEP_SHORTEST_PATH
+                ));
+            }
+        };
+
+        Ok(rule)
+    }
+}
+
+/// Configuration of an action on the duplicates of a report, built by chaining its methods.
+///
+/// Actions are dry runs by default : they only tell what would be done until
+/// [`ActionOptions::dry_run`] is set to `false`.
+#[derive(Clone, Debug)]
+pub struct ActionOptions {
+    pub(crate) action: Action,
+    pub(crate) keep: KeepRule,
+    pub(crate) keep_patterns: Vec<Pattern>,
+    pub(crate) dry_run: bool,
+    pub(crate) absolute_links: bool,
+    pub(crate) quarantine: PathBuf,
+    pub(crate) log: Option<PathBuf>,
+}
+
+impl ActionOptions {
+    pub fn new(action: Action) -> Self {
+        ActionOptions {
+            action,
+            keep: KeepRule::default(),
+            keep_patterns: Vec::new(),
+            dry_run: true,
+            absolute_links: false,
+            quarantine: PathBuf::from(DEFAULT_QUARANTINE),
+            log: (action == Action::Symlink).then(|| PathBuf::from(DEFAULT_SYMLINK_LOG)),
+        }
+    }
+
+    pub fn keep(mut self, keep: KeepRule) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Prefers keeping the files matching `pattern`, before those matching the patterns added
+    /// after it and the other files. The keep rule then chooses among the preferred files.
+    pub fn keep_pattern(mut self, pattern: Pattern) -> Self {
+        self.keep_patterns.push(pattern);
+        self
+    }
+
+    /// Only tells what would be done, without touching any file.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Makes the symlinks of [`Action::Symlink`] point to the absolute path of the copy kept,
+    /// instead of a path relative to their directory, which still works when both are moved
+    /// together.
+    pub fn absolute_links(mut self, absolute_links: bool) -> Self {
+        self.absolute_links = absolute_links;
+        self
+    }
+
+    /// Directory the duplicates are moved to by [`Action::MoveTo`], created if needed. Each one
+    /// keeps its path relative to its root, under a directory named after the number of the root
+    /// (from `1`) when the report has several of them. The moves are also appended to the
+    /// [`QUARANTINE_MANIFEST`] log of the directory.
+    pub fn quarantine(mut self, quarantine: impl Into<PathBuf>) -> Self {
+        self.quarantine = quarantine.into();
+        self
+    }
+
+    /// Appends each path the action is done on to the log at `log`, see
+    /// [`read_action_log`](crate::read_action_log), so it can be undone with
+    /// [`undo_action_log`](crate::undo_action_log). Nothing is written for a dry run. The symlink
+    /// action writes to [`DEFAULT_SYMLINK_LOG`] unless another log is given.
+    pub fn log(mut self, log: impl Into<PathBuf>) -> Self {
+        self.log = Some(log.into());
+        self
+    }
+}
+
+/// Result of an action on the duplicates of a report.
+#[derive(Debug)]
+pub struct ActionReport {
+    pub action: Action,
+    pub dry_run: bool,
+    /// Log the action was written to, if any.
+    pub log: Option<PathBuf>,
+    /// Duplicates the action was done on, or would be done on for a dry run.
+    pub steps: Vec<ActionStep>,
+    /// Files left untouched because of an error, with the rest of their group when it concerns the
+    /// copy to keep.
+    pub errors: Vec<FindDoublesError>,
+}
+
+impl ActionReport {
+    /// Space freed by the action, or that would be freed for a dry run. For [`Action::MoveTo`], it
+    /// is the size of the files moved, only freed once the quarantine is emptied.
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.steps.iter().map(|step| step.size).sum()
+    }
+}
+
+/// Duplicate the action was done on.
+#[derive(Clone, Debug)]
+pub struct ActionStep {
+    pub path: PathBuf,
+    /// Copy kept with the same content.
+    pub kept: PathBuf,
+    pub size: u64,
+    /// Where the duplicate is moved by [`Action::MoveTo`].
+    pub destination: Option<PathBuf>,
+}
+
+/// Does the action of `options` on the duplicates of each group of `report`, keeping one copy
+/// chosen by the keep rule, or all the reference files when the group has some. Reference files
+/// are never touched.
+///
+/// The action is also done on the other hard links of a duplicate found by the scan, as its space
+/// is only reclaimed once all of them are replaced. Right before acting on a path, the size and
+/// the hash of the copy kept are computed again, and the path is compared byte by byte with it,
+/// whatever the hash algorithm of the scan : if the copy kept changed since the scan, its whole
+/// group is left untouched, and so is a path whose content differs or that is the same file as the
+/// copy kept.
+///
+/// If the log of the options or the quarantine manifest can't be opened, no file is touched.
+pub fn apply_action(report: &DuplicateReport, options: &ActionOptions) -> ActionReport {
+    let hash_options = HashOptions {
+        algorithm: report.hash_algorithm.unwrap_or_default(),
+        ..HashOptions::default()
+    };
+    let mut action_report = ActionReport {
+        action: options.action,
+        dry_run: options.dry_run,
+        log: options.log.clone().filter(|_| !options.dry_run),
+        steps: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    let mut logs = Vec::new();
+    if !options.dry_run {
+        let manifest = if options.action == Action::MoveTo {
+            if let Err(err) = fs::create_dir_all(&options.quarantine) {
+                let err = FindDoublesError::io(&options.quarantine, Operation::CreateDir, err);
+                action_report.errors.push(err);
+                return action_report;
+            }
+            Some(options.quarantine.join(QUARANTINE_MANIFEST))
+        } else {
+            None
+        };
+
+        for path in manifest.iter().chain(&options.log) {
+            match ActionLog::open(path) {
+                Ok(log) => logs.push(log),
+                Err(err) => {
+                    action_report.errors.push(err);
+                    return action_report;
+                }
+            }
+        }
+    }
+
+    // The first path of each file with several hard links is the one in the groups.
+    let links: HashMap<&Path, &[DuplicateFile]> = report
+        .hard_links
+        .iter()
+        .map(|group| (group.files[0].path.as_path(), &group.files[1..]))
+        .collect();
+
+    for group in report.groups.iter() {
+        if let Err(err) = apply_on_group(
+            group,
+            &links,
+            &report.roots,
+            options,
+            hash_options,
+            &mut logs,
+            &mut action_report,
+        ) {
+            action_report.errors.push(err);
+        }
+    }
+
+    action_report
+}
+
+fn apply_on_group(
+    group: &DuplicateGroup,
+    links: &HashMap<&Path, &[DuplicateFile]>,
+    roots: &[PathBuf],
+    options: &ActionOptions,
+    hash_options: HashOptions,
+    logs: &mut [ActionLog],
+    action_report: &mut ActionReport,
+) -> Result<(), FindDoublesError> {
+    let has_references = group.files.iter().any(|file| file.reference);
+    let Some(kept) = choose_kept(group, has_references, options)? else {
+        return Ok(());
+    };
+    // Symlinks are acted on first, so that a symlink to another duplicate isn't left dangling.
+    let mut duplicates: Vec<_> = group
+        .files
+        .iter()
+        .filter(|file| file.path != kept.path && !(has_references && file.reference))
+        .map(|file| (is_symlink(&file.path), file))
+        .collect();
+    duplicates.sort_by_key(|(is_symlink, _)| !is_symlink);
+
+    let kept_check = if options.dry_run {
+        None
+    } else {
+        let expected = match &group.key {
+            FileKey::Hash(digest) | FileKey::Both(_, digest) => Some((group.size, *digest)),
+            FileKey::Name(_) => None,
+        };
+        Some(check_file(&kept.path, expected, hash_options)?)
+    };
+
+    for (symlink, file) in duplicates {
+        // Only follows the real hard links of the file, a symlink being a path of its own.
+        let other_links = links.get(file.path.as_path()).copied().unwrap_or_default();
+        let paths: Vec<_> = [file]
+            .into_iter()
+            .chain(other_links.iter().filter(|link| !is_symlink(&link.path)))
+            .collect();
+
+        let mut done = 0;
+        for file in paths.iter() {
+            let destination = (options.action == Action::MoveTo)
+                .then(|| quarantine_path(&options.quarantine, roots, file));
+            let result = match &kept_check {
+                Some(kept_check) => act_on_duplicate(
+                    &file.path,
+                    destination.as_deref(),
+                    &kept.path,
+                    kept_check,
+                    options,
+                    hash_options,
+                ),
+                None => Ok(()),
+            };
+            match result {
+                Ok(()) => {
+                    done += 1;
+                    let step = ActionStep {
+                        path: file.path.clone(),
+                        kept: kept.path.clone(),
+                        size: 0,
+                        destination,
+                    };
+                    for log in logs.iter_mut() {
+                        if let Err(err) = log.record(options.action, &step) {
+                            action_report.errors.push(err);
+                        }
+                    }
+                    action_report.steps.push(step);
+                }
+                Err(err) => action_report.errors.push(err),
+            }
+        }
+
+        // The space of the file is only reclaimed once the action is done on all its paths, while
+        // a symlink doesn't hold any.
+        if done == paths.len() && !symlink {
+            if let Some(step) = action_report.steps.last_mut() {
+                step.size = kept_check.as_ref().map_or(group.size, |check| check.size);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the duplicate at `path` still has the content of the copy kept, without being the same
+/// file, before acting on it.
+fn act_on_duplicate(
+    path: &Path,
+    destination: Option<&Path>,
+    kept: &Path,
+    kept_check: &FileCheck,
+    options: &ActionOptions,
+    hash_options: HashOptions,
+) -> Result<(), FindDoublesError> {
+    let check = check_file(path, None, hash_options)?;
+    if check.id.is_some() && check.id == kept_check.id {
+        return Err(FindDoublesError::SameFile {
+            path: path.to_owned(),
+            kept: kept.to_owned(),
+        });
+    }
+    // Whatever the hash algorithm of the scan, the contents are compared byte by byte.
+    let stats = ScanStats::default();
+    if check.size != kept_check.size || !same_content(kept, path, hash_options.buffer_size, &stats)?
+    {
+        return Err(FindDoublesError::Changed {
+            path: path.to_owned(),
+        });
+    }
+
+    match options.action {
+        Action::Delete => {
+            fs::remove_file(path).map_err(|err| FindDoublesError::io(path, Operation::Remove, err))
+        }
+        Action::HardLink => {
+            let device = |check: &FileCheck| check.id.map(|(device, _)| device);
+            if device(&check) != device(kept_check) {
+                return Err(FindDoublesError::OtherDevice {
+                    path: path.to_owned(),
+                    kept: kept.to_owned(),
+                });
+            }
+            // `hard_link` doesn't follow symlinks, so it is given the file itself.
+            replace_with(
+                path,
+                |tmp_path| fs::hard_link(fs::canonicalize(kept)?, tmp_path),
+                Operation::Link,
+            )
+        }
+        Action::Reflink => reflink(kept, path),
+        Action::Symlink => replace_with(
+            path,
+            |tmp_path| {
+                let target = if options.absolute_links {
+                    canonical_path(kept)?
+                } else {
+                    relative_path(&canonical_parent(path)?, &canonical_path(kept)?)
+                };
+                symlink(&target, tmp_path)
+            },
+            Operation::Link,
+        ),
+        Action::MoveTo => move_file(path, destination.expect("moves have a destination")),
+    }
+}
+
+/// Path of `file` in the `quarantine` directory, relative to its root.
+fn quarantine_path(quarantine: &Path, roots: &[PathBuf], file: &DuplicateFile) -> PathBuf {
+    let relative = roots
+        .get(file.root)
+        .and_then(|root| file.path.strip_prefix(root).ok())
+        .unwrap_or(&file.path);
+
+    let mut destination = quarantine.to_owned();
+    if roots.len() > 1 {
+        destination.push((file.root + 1).to_string());
+    }
+    // Only keeps the names, so the destination stays in the quarantine.
+    destination.extend(
+        relative
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_))),
+    );
+    destination
+}
+
+/// Moves the file at `path` to `destination`, creating its missing directories but never replacing
+/// an existing file. Across filesystems, the file is copied, the copy compared byte by byte with
+/// the file, then the file is removed.
+pub(crate) fn move_file(path: &Path, destination: &Path) -> Result<(), FindDoublesError> {
+    if fs::symlink_metadata(destination).is_ok() {
+        let err = io::Error::from(ErrorKind::AlreadyExists);
+        return Err(FindDoublesError::io(destination, Operation::Rename, err));
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| FindDoublesError::io(parent, Operation::CreateDir, err))?;
+    }
+
+    match fs::rename(path, destination) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+            fs::copy(path, destination)
+                .map_err(|err| FindDoublesError::io(destination, Operation::Copy, err))?;
+            match same_content(
+                path,
+                destination,
+                DEFAULT_BUFFER_SIZE,
+                &ScanStats::default(),
+            ) {
+                Ok(true) => fs::remove_file(path)
+                    .map_err(|err| FindDoublesError::io(path, Operation::Remove, err)),
+                result => {
+                    let _ = fs::remove_file(destination);
+                    Err(result.err().unwrap_or_else(|| FindDoublesError::Changed {
+                        path: path.to_owned(),
+                    }))
+                }
+            }
+        }
+        Err(err) => Err(FindDoublesError::io(path, Operation::Rename, err)),
+    }
+}
+
+/// Absolute path of `path` without `.`, `..` or symlinks in its directories, the file itself being
+/// left as is.
+fn canonical_path(path: &Path) -> io::Result<PathBuf> {
+    Ok(canonical_parent(path)?.join(path.file_name().unwrap_or_default()))
+}
+
+fn canonical_parent(path: &Path) -> io::Result<PathBuf> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::canonicalize(parent),
+        _ => fs::canonicalize("."),
+    }
+}
+
+/// Path of `target` relative to the directory `dir`, both being absolute and canonical.
+fn relative_path(dir: &Path, target: &Path) -> PathBuf {
+    let common = dir
+        .components()
+        .zip(target.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    dir.components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .chain(target.components().skip(common))
+        .collect()
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, path)
+}
+
+/// Atomically replaces the file at `path` by the one `create` makes at a temporary path in the same
+/// directory, renaming it over `path`.
+pub(crate) fn replace_with(
+    path: &Path,
+    create: impl FnOnce(&Path) -> io::Result<()>,
+    operation: Operation,
+) -> Result<(), FindDoublesError> {
+    let tmp_path = temporary_path(path);
+    // A file already at the temporary path isn't ours, so it is neither replaced nor removed.
+    if fs::symlink_metadata(&tmp_path).is_ok() {
+        let err = io::Error::from(ErrorKind::AlreadyExists);
+        return Err(FindDoublesError::io(&tmp_path, operation, err));
+    }
+    create(&tmp_path).map_err(|err| {
+        if err.kind() != ErrorKind::AlreadyExists {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        FindDoublesError::io(path, operation, err)
+    })?;
+
+    fs::rename(&tmp_path, path).map_err(|err| {
+        let _ = fs::remove_file(&tmp_path);
+        FindDoublesError::io(path, Operation::Rename, err)
+    })
+}
+
+/// Hidden path next to `path`, for the file replacing it.
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".find_doubles-{}", process::id()));
+    path.with_file_name(name)
+}
+
+/// Chooses the copy to keep among the reference files if there are some, otherwise among all the
+/// files of the group. Symlinks are never kept, as their target may be outside of the scan and
+/// would be the last copy left : a group without any other file to keep is left untouched.
+fn choose_kept<'a>(
+    group: &'a DuplicateGroup,
+    has_references: bool,
+    options: &ActionOptions,
+) -> Result<Option<&'a DuplicateFile>, FindDoublesError> {
+    let mut candidates = Vec::with_capacity(group.files.len());
+    for file in group.files.iter() {
+        if (has_references && !file.reference) || file.symlink || is_symlink(&file.path) {
+            continue;
+        }
+
+        let pattern_rank = options
+            .keep_patterns
+            .iter()
+            .position(|pattern| pattern.matches(&file.path))
+            .unwrap_or(options.keep_patterns.len());
+        let modified = match options.keep {
+            KeepRule::Oldest | KeepRule::Newest => Some(modified(&file.path)?),
+            KeepRule::FirstRoot | KeepRule::ShortestPath => None,
+        };
+        candidates.push((file, pattern_rank, modified));
+    }
+    if candidates.is_empty() {
+        return Err(FindDoublesError::OnlySymlinks {
+            path: group.files[0].path.clone(),
+        });
+    }
+
+    Ok(candidates
+        .into_iter()
+        .min_by(
+            |(file_a, rank_a, modified_a), (file_b, rank_b, modified_b)| {
+                let by_rule = match options.keep {
+                    KeepRule::FirstRoot => file_a.root.cmp(&file_b.root),
+                    KeepRule::Oldest => modified_a.cmp(modified_b),
+                    KeepRule::Newest => modified_b.cmp(modified_a),
+                    KeepRule::ShortestPath => file_a
+                        .path
+                        .as_os_str()
+                        .len()
+                        .cmp(&file_b.path.as_os_str().len()),
+                };
+                rank_a
+                    .cmp(rank_b)
+                    .then(by_rule)
+                    .then_with(|| file_a.path.cmp(&file_b.path))
+            },
+        )
+        .map(|(file, _, _)| file))
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink())
+}
+
+fn modified(path: &Path) -> Result<SystemTime, FindDoublesError> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|err| FindDoublesError::io(path, Operation::ReadMetadata, err))
+}
+
+/// Size and identifier of a file, read right before acting on its group.
+struct FileCheck {
+    size: u64,
+    id: Option<(u64, u64)>,
+}
+
+/// Reads again the size of the file at `path` and, when `expected` is given, computes again its
+/// hash, checking they are the expected ones.
+fn check_file(
+    path: &Path,
+    expected: Option<(u64, Digest)>,
+    hash_options: HashOptions,
+) -> Result<FileCheck, FindDoublesError> {
+    let metadata = fs::metadata(path)
+        .map_err(|err| FindDoublesError::io(path, Operation::ReadMetadata, err))?;
+    if expected.is_some_and(|(size, _)| size != metadata.len()) {
+        return Err(FindDoublesError::Changed {
+            path: path.to_owned(),
+        });
+    }
+
+    if let Some((_, expected_digest)) = expected {
+        if get_hash(path, hash_options, &ScanStats::default())? != expected_digest {
+            return Err(FindDoublesError::Changed {
+                path: path.to_owned(),
+            });
+        }
+    }
+
+    Ok(FileCheck {
+        size: metadata.len(),
+        id: file_id(&metadata),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use crate::test_dir::TestDir;
+    use crate::{find_doubles, Comparison, ScanOptions, SymlinkPolicy};
+
+    fn scan(dir: &TestDir, options: ScanOptions) -> DuplicateReport {
+        find_doubles(
+            &[dir.path().to_owned()],
+            &options.comparison(Comparison::Hash),
+        )
+    }
+
+    fn glob(dir: &TestDir, name: &str) -> Pattern {
+        Pattern::glob(&dir.path().join(name).to_string_lossy()).unwrap()
+    }
+
+    /// Copy kept by a dry run of `options` on the single group of `report`.
+    fn kept_path(report: &DuplicateReport, options: ActionOptions) -> PathBuf {
+        let action_report = apply_action(report, &options);
+        assert!(
+            action_report.errors.is_empty(),
+            "{:?}",
+            action_report.errors
+        );
+        let step = &action_report.steps[0];
+        assert!(action_report
+            .steps
+            .iter()
+            .all(|other| other.kept == step.kept));
+        step.kept.clone()
+    }
+
+    #[test]
+    fn delete_keeps_one_copy() {
+        let dir = TestDir::new();
+        let a = dir.write("a", "hello");
+        let b = dir.write("sub/b", "hello");
+        let c = dir.write("c", "hello");
+        let other = dir.write("other", "world");
+
+        let report = scan(&dir, ScanOptions::new());
+        let action_report =
+            apply_action(&report, &ActionOptions::new(Action::Delete).dry_run(false));
+
+        assert!(
+            action_report.errors.is_empty(),
+            "{:?}",
+            action_report.errors
+        );
+        assert_eq!(action_report.steps.len(), 2);
+        assert_eq!(action_report.reclaimed_bytes(), 10);
+        assert!(a.exists());
+        assert!(!b.exists());
+        assert!(!c.exists());
+        assert!(other.exists());
+    }
+
+    #[test]
+    fn dry_run_touches_nothing() {
+        let dir = TestDir::new();
+        let a = dir.write("a", "hello");
+        let b = dir.write("b", "hello");
+
+        let report = scan(&dir, ScanOptions::new());
+        let action_report = apply_action(&report, &ActionOptions::new(Action::Delete));
+
+        assert!(action_report.dry_run);
+        assert_eq!(action_report.steps.len(), 1);
+        assert!(a.exists());
+        assert!(b.exists());
+    }
+
+    #[test]
+    fn keep_rules_choose_the_kept_copy() {
+        let dir = TestDir::new();
+        let long = dir.write("sub/dir/a", "hello");
+        let short = dir.write("b", "hello");
+        let old = dir.write("c", "hello");
+        let now = SystemTime::now();
+        for (path, age) in [(&long, 20), (&short, 10), (&old, 30)] {
+            let time = now - std::time::Duration::from_secs(age * 3600);
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        }
+        let report = scan(&dir, ScanOptions::new());
+
+        let keep = |rule| ActionOptions::new(Action::Delete).keep(rule);
+        assert_eq!(kept_path(&report, keep(KeepRule::FirstRoot)), short);
+        assert_eq!(kept_path(&report, keep(KeepRule::ShortestPath)), short);
+        assert_eq!(kept_path(&report, keep(KeepRule::Oldest)), old);
+        assert_eq!(kept_path(&report, keep(KeepRule::Newest)), short);
+        let pattern = keep(KeepRule::Newest).keep_pattern(glob(&dir, "sub/**"));
+        assert_eq!(kept_path(&report, pattern), long);
+    }
+
+    #[test]
+    fn keeps_the_reference_files() {
+        let dir = TestDir::new();
+        let candidate = dir.write("candidates/a", "hello");
+        let reference = dir.write("references/a", "hello");
+        let copy = dir.write("references/b", "hello");
+
+        let options = ScanOptions::new()
+            .comparison(Comparison::Hash)
+            .reference(dir.path().join("references"));
+        let report = find_doubles(&[dir.path().join("candidates")], &options);
+        let action_report =
+            apply_action(&report, &ActionOptions::new(Action::Delete).dry_run(false));
+
+        assert!(
+            action_report.errors.is_empty(),
+            "{:?}",
+            action_report.errors
+        );
+        assert!(!candidate.exists());
+        assert!(reference.exists());
+        assert!(copy.exists());
+    }
+
+    #[test]
+    fn refuses_a_changed_duplicate() {
+        let dir = TestDir::new();
+        let a = dir.write("a", "hello");
+        let b = dir.write("b", "hello");
+
+        let report = scan(
+            &dir,
+            ScanOptions::new().hash_algorithm(crate::HashAlgorithm::Xxh3),
+        );
+        fs::write(&b, "jello").unwrap();
+        let action_report =
+            apply_action(&report, &ActionOptions::new(Action::Delete).dry_run(false));
+
+        assert!(action_report.steps.is_empty());
+        assert_eq!(
+            action_report.errors,
+            [FindDoublesError::Changed { path: b.clone() }]
+        );
+        assert!(a.exists());
+        assert!(b.exists());
+    }
+
+    #[test]
+    fn refuses_a_group_whose_kept_copy_changed() {
+        let dir = TestDir::new();
+        let a = dir.write("a", "hello");
+        let b = dir.write("b", "hello");
+        let c = dir.write("c", "hello");
+
+        let report = scan(&dir, ScanOptions::new());
+        fs::write(&a, "jello").unwrap();
+        let action_report =
+            apply_action(&report, &ActionOptions::new(Action::Delete).dry_run(false));
+
+        assert!(action_report.steps.is_empty());
+        assert_eq!(
+            action_report.errors,
+            [FindDoublesError::Changed { path: a.clone() }]
+        );
+        assert!(b.exists());
+        assert!(c.exists());
+    }
+
+    #[test]
+    fn never_deletes_the_last_copy() {
+        let dir = TestDir::new();
+        let a = dir.write("one/a", "hello");
+        let link = dir.path().join("two/a");
+        fs::create_dir_all(link.parent().unwrap()).unwrap();
+        fs::hard_link(&a, &link).unwrap();
+
+        // Comparing names keeps both hard links in the group.
+        let report = find_doubles(&[dir.path().to_owned()], &ScanOptions::new());
+        let action_report =
+            apply_action(&report, &ActionOptions::new(Action::Delete).dry_run(false));
+
+        assert!(action_report.steps.is_empty());
+        assert_eq!(
+            action_report.errors,
+            [FindDoublesError::SameFile {
+                path: link.clone(),
+                kept: a.clone(),
+            }]
+        );
+        assert!(a.exists());
+        assert!(link.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_link_replaces_the_duplicate() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TestDir::new();
+        let a = dir.write("a", "hello");
+        let b = dir.write("b", "hello");
+
+        let report = scan(&dir, ScanOptions::new());
+        let options = ActionOptions::new(Action::HardLink).dry_run(false);
+        let action_report = apply_action(&report, &options);
+
+        assert!(
+            action_report.errors.is_empty(),
+            "{:?}",
+            action_report.errors
+        );
+        assert_eq!(action_report.reclaimed_bytes(), 5);
+        assert_eq!(
+            fs::metadata(&a).unwrap().ino(),
+            fs::metadata(&b).unwrap().ino()
+        );
+        assert!(!temporary_path(&b).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_link_is_never_made_to_a_symlink() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TestDir::new();
+        let a = dir.write("a", "hello");
+        let b = dir.write("sub/b", "hello");
+        let link = dir.path().join("l");
+        std::os::unix::fs::symlink("a", &link).unwrap();
+
+        let report = scan(&dir, ScanOptions::new().symlinks(SymlinkPolicy::Report));
+        let options = ActionOptions::new(Action::HardLink)
+            .keep_pattern(glob(&dir, "l"))
+            .dry_run(false);
+        let action_report = apply_action(&report, &options);
+
+        // The symlink itself is left as it is, being the copy kept through another path.
+        assert_eq!(
+            action_report.errors,
+            [FindDoublesError::SameFile {
+                path: link.clone(),
+                kept: a.clone(),
+            }]
+        );
+        assert_eq!(action_report.reclaimed_bytes(), 5);
+        assert!(!fs::symlink_metadata(&b).unwrap().is_symlink());
+        assert_eq!(
+            fs::metadata(&a).unwrap().ino(),
+            fs::metadata(&b).unwrap().ino()
+        );
+        assert_eq!(fs::read_to_string(&link).unwrap(), "hello");
+        assert!(a.exists());
+    }
+
+    #[test]
+    fn replace_leaves_an_existing_temporary_file() {
+        let dir = TestDir::new();
+        let a = dir.write("a", "hello");
+        let b = dir.write("b", "hello");
+        let tmp_path = temporary_path(&b);
+        fs::write(&tmp_path, "not ours").unwrap();
+
+        let result = replace_with(&b, |tmp_path| fs::hard_link(&a, tmp_path), Operation::Link);
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&tmp_path).unwrap(), "not ours");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "hello");
+    }
+
+    #[test]
+    fn relative_path_climbs_to_the_common_ancestor() {
+        let relative = |dir: &str, target: &str| relative_path(Path::new(dir), Path::new(target));
+
+        assert_eq!(relative("/a/b", "/a/b/f"), Path::new("f"));
+        assert_eq!(relative("/a/b", "/a/c/f"), Path::new("../c/f"));
+        assert_eq!(relative("/a/b/c", "/d/f"), Path::new("../../../d/f"));
+        assert_eq!(relative("/", "/a/f"), Path::new("a/f"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_replaces_the_duplicate_and_is_undone() {
+        let dir = TestDir::new();
+        let a = dir.write("a/kept", "hello");
+        let b = dir.write("b/copy", "hello");
+        let log = dir.path().join("log.tsv");
+
+        let report = find_doubles(
+            &[dir.path().join("a"), dir.path().join("b")],
+            &ScanOptions::new().comparison(Comparison::Hash),
+        );
+        let options = ActionOptions::new(Action::Symlink).log(&log).dry_run(false);
+        let action_report = apply_action(&report, &options);
+
+        assert!(
+            action_report.errors.is_empty(),
+            "{:?}",
+            action_report.errors
+        );
+        assert_eq!(action_report.log.as_ref(), Some(&log));
+        assert_eq!(fs::read_link(&b).unwrap(), Path::new("../a/kept"));
+        assert_eq!(fs::read_to_string(&b).unwrap(), "hello");
+
+        let undo_report = crate::undo_action_log(&log, false).unwrap();
+
+        assert!(undo_report.errors.is_empty(), "{:?}", undo_report.errors);
+        assert_eq!(undo_report.restored.len(), 1);
+        assert!(!fs::symlink_metadata(&b).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&b).unwrap(), "hello");
+        assert!(a.exists());
+    }
+
+    #[test]
+    fn symlink_action_logs_by_default() {
+        let options = ActionOptions::new(Action::Symlink);
+        assert_eq!(options.log, Some(PathBuf::from(DEFAULT_SYMLINK_LOG)));
+        assert_eq!(ActionOptions::new(Action::Delete).log, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn never_keeps_a_symlink() {
+        let dir = TestDir::new();
+        let outside = dir.write("outside/a", "hello");
+        let b = dir.write("root/b", "hello");
+        let links = ["root/l1", "root/l2"].map(|name| dir.path().join(name));
+        for link in &links {
+            std::os::unix::fs::symlink("../outside/a", link).unwrap();
+        }
+        let options = ScanOptions::new()
+            .comparison(Comparison::Hash)
+            .symlinks(SymlinkPolicy::Follow);
+
+        // The regular file is kept whatever the pattern.
+        let report = find_doubles(&[dir.path().join("root")], &options);
+        let action = ActionOptions::new(Action::Delete).keep_pattern(glob(&dir, "root/l*"));
+        assert_eq!(kept_path(&report, action), b);
+
+        // Without any, the group is left untouched.
+        fs::remove_file(&b).unwrap();
+        let report = find_doubles(&[dir.path().join("root")], &options);
+        let action_report =
+            apply_action(&report, &ActionOptions::new(Action::Delete).dry_run(false));
+
+        assert!(action_report.steps.is_empty());
+        assert!(matches!(
+            action_report.errors[..],
+            [FindDoublesError::OnlySymlinks { .. }]
+        ));
+        assert!(links.iter().all(|link| link.exists()));
+        assert!(outside.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_to_a_duplicate_is_deleted_before_its_target() {
+        let dir = TestDir::new();
+        let a = dir.write("a", "hello");
+        let b = dir.write("b", "hello");
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("a", &link).unwrap();
+
+        let report = scan(&dir, ScanOptions::new().symlinks(SymlinkPolicy::Report));
+        let options = ActionOptions::new(Action::Delete)
+            .keep_pattern(glob(&dir, "b"))
+            .dry_run(false);
+        let action_report = apply_action(&report, &options);
+
+        assert!(
+            action_report.errors.is_empty(),
+            "{:?}",
+            action_report.errors
+        );
+        assert_eq!(action_report.steps.len(), 2);
+        assert_eq!(action_report.reclaimed_bytes(), 5);
+        assert!(fs::symlink_metadata(&link).is_err());
+        assert!(!a.exists());
+        assert!(b.exists());
+    }
+}
@@ -0,0 +1,265 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::action::{move_file, replace_with};
+use super::list::path_from_bytes;
+use super::{Action, ActionStep, FindDoublesError, Operation};
+
+/// Log of the duplicates an action was done on, one line per path : the name of the action, the
+/// absolute path, the absolute path of the copy kept and for moves the absolute path the file was
+/// moved to, separated by tabs. Backslashes, tabs and newlines in paths are escaped
+/// with a backslash, so any path can be read back.
+pub struct ActionLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl ActionLog {
+    /// Opens the log at `path`, appending to it if it already exists.
+    pub fn open(path: &Path) -> Result<Self, FindDoublesError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| FindDoublesError::io(path, Operation::Open, err))?;
+
+        Ok(ActionLog {
+            path: path.to_owned(),
+            file,
+        })
+    }
+
+    /// Writes the line of `step`, flushed right away so it isn't lost if the process stops.
+    pub fn record(&mut self, action: Action, step: &ActionStep) -> Result<(), FindDoublesError> {
+        let mut line = action.name().as_bytes().to_vec();
+        for path in [&step.path, &step.kept]
+            .into_iter()
+            .chain(&step.destination)
+        {
+            let path = std::path::absolute(path)
+                .map_err(|err| FindDoublesError::io(path, Operation::ReadMetadata, err))?;
+            line.push(b'\t');
+            escape(&path, &mut line);
+        }
+        line.push(b'\n');
+
+        self.file
+            .write_all(&line)
+            .and_then(|()| self.file.flush())
+            .map_err(|err| FindDoublesError::io(&self.path, Operation::Write, err))
+    }
+}
+
+/// Entry of an action log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub action: String,
+    pub path: PathBuf,
+    pub kept: PathBuf,
+    pub destination: Option<PathBuf>,
+}
+
+/// Reads the entries of the action log at `path`, skipping the lines that aren't entries.
+pub fn read_action_log(path: &Path) -> io::Result<Vec<LogEntry>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    let mut line = Vec::new();
+
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+
+        let fields: Vec<_> = line.split(|&byte| byte == b'\t').collect();
+        if let [action, path, kept, ref destination @ ..] = fields[..] {
+            if destination.len() <= 1 {
+                entries.push(LogEntry {
+                    action: String::from_utf8_lossy(action).into_owned(),
+                    path: path_from_bytes(unescape(path)),
+                    kept: path_from_bytes(unescape(kept)),
+                    destination: destination
+                        .first()
+                        .map(|destination| path_from_bytes(unescape(destination))),
+                });
+            }
+        }
+        line.clear();
+    }
+
+    Ok(entries)
+}
+
+/// Result of undoing the entries of an action log.
+#[derive(Debug)]
+pub struct UndoReport {
+    pub dry_run: bool,
+    /// Entries undone, or that would be undone for a dry run.
+    pub restored: Vec<LogEntry>,
+    /// Entries left as they are because of an error.
+    pub errors: Vec<FindDoublesError>,
+}
+
+/// Undoes the entries of the action log at `log`, the latest first. Each symlink still pointing to
+/// the copy kept is replaced by a copy of it, made under a temporary name and renamed over the
+/// symlink, and each moved file is moved back if nothing took its place. Entries of other actions
+/// can't be undone and are reported as errors.
+pub fn undo_action_log(log: &Path, dry_run: bool) -> Result<UndoReport, FindDoublesError> {
+    let entries =
+        read_action_log(log).map_err(|err| FindDoublesError::io(log, Operation::Open, err))?;
+    let mut report = UndoReport {
+        dry_run,
+        restored: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for entry in entries.into_iter().rev() {
+        match undo_entry(&entry, dry_run) {
+            Ok(()) => report.restored.push(entry),
+            Err(err) => report.errors.push(err),
+        }
+    }
+
+    Ok(report)
+}
+
+fn undo_entry(entry: &LogEntry, dry_run: bool) -> Result<(), FindDoublesError> {
+    match (entry.action.parse(), &entry.destination) {
+        (Ok(Action::Symlink), _) => {
+            let kept = fs::canonicalize(&entry.kept)
+                .map_err(|err| FindDoublesError::io(&entry.kept, Operation::ReadMetadata, err))?;
+            let is_symlink = fs::symlink_metadata(&entry.path)
+                .map_err(|err| FindDoublesError::io(&entry.path, Operation::ReadMetadata, err))?
+                .file_type()
+                .is_symlink();
+            if !is_symlink || fs::canonicalize(&entry.path).ok() != Some(kept) {
+                return Err(FindDoublesError::Changed {
+                    path: entry.path.clone(),
+                });
+            }
+
+            if dry_run {
+                return Ok(());
+            }
+            replace_with(
+                &entry.path,
+                |tmp_path| fs::copy(&entry.kept, tmp_path).map(drop),
+                Operation::Copy,
+            )
+        }
+        (Ok(Action::MoveTo), Some(destination)) => {
+            fs::symlink_metadata(destination)
+                .map_err(|err| FindDoublesError::io(destination, Operation::ReadMetadata, err))?;
+
+            if dry_run {
+                if fs::symlink_metadata(&entry.path).is_ok() {
+                    let err = io::Error::from(ErrorKind::AlreadyExists);
+                    return Err(FindDoublesError::io(&entry.path, Operation::Rename, err));
+                }
+                return Ok(());
+            }
+            move_file(destination, &entry.path)
+        }
+        _ => Err(FindDoublesError::NotUndoable {
+            path: entry.path.clone(),
+            action: entry.action.clone(),
+        }),
+    }
+}
+
+fn escape(path: &Path, line: &mut Vec<u8>) {
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'\\' => line.extend_from_slice(b"\\\\"),
+            b'\t' => line.extend_from_slice(b"\\t"),
+            b'\n' => line.extend_from_slice(b"\\n"),
+            byte => line.push(byte),
+        }
+    }
+}
+
+fn unescape(field: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut escaped = false;
+
+    for &byte in field {
+        if escaped {
+            bytes.push(match byte {
+                b't' => b'\t',
+                b'n' => b'\n',
+                byte => byte,
+            });
+            escaped = false;
+        } else if byte == b'\\' {
+            escaped = true;
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn escaped_paths_are_read_back() {
+        let dir = TestDir::new();
+        let log_path = dir.path().join("log.tsv");
+        let step = ActionStep {
+            path: dir.path().join("tab\there\\nline\nback\\slash"),
+            kept: dir.path().join("new\nline"),
+            size: 5,
+            destination: Some(dir.path().join("quarantine/a\tb")),
+        };
+        let plain = ActionStep {
+            path: dir.path().join("a"),
+            kept: dir.path().join("b"),
+            size: 5,
+            destination: None,
+        };
+
+        let mut log = ActionLog::open(&log_path).unwrap();
+        log.record(Action::MoveTo, &step).unwrap();
+        log.record(Action::Symlink, &plain).unwrap();
+
+        let entries = read_action_log(&log_path).unwrap();
+        assert_eq!(
+            entries,
+            [
+                LogEntry {
+                    action: Action::MoveTo.name().to_owned(),
+                    path: step.path,
+                    kept: step.kept,
+                    destination: step.destination,
+                },
+                LogEntry {
+                    action: Action::Symlink.name().to_owned(),
+                    path: plain.path,
+                    kept: plain.kept,
+                    destination: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn deletions_are_not_undone() {
+        let dir = TestDir::new();
+        let log_path = dir.write("log.tsv", "delete\t/gone\t/kept\nnot an entry\n");
+
+        let report = undo_action_log(&log_path, false).unwrap();
+
+        assert!(report.restored.is_empty());
+        assert_eq!(
+            report.errors,
+            [FindDoublesError::NotUndoable {
+                path: PathBuf::from("/gone"),
+                action: "delete".to_owned(),
+            }]
+        );
+    }
+}
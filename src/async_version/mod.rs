@@ -1,64 +1,135 @@
 extern crate smol;
 
 use smol::channel::{unbounded, Sender};
-use smol::fs::{read, read_dir};
+use smol::fs::{read_dir, File};
+use smol::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, SeekFrom};
 use smol::lock::Semaphore;
 use smol::stream::StreamExt;
 use smol::LocalExecutor;
-use smol::{pin, Task};
-use std::collections::HashMap;
-use std::fmt::Write;
+use smol::Task;
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
 
-use sha3::{Digest, Sha3_256};
-
-use super::{Comparison, CD, CF};
+use super::walk::{classify, list_files, Dir, Entry, FoundFile};
+use super::{
+    find_doubles_by_stages, get_file_name, Digest, DuplicateGroup, FileKey, FindDoublesError,
+    HardLinkGroup, HashOptions, Hasher, Operation, Phase, ScanOptions, ScanStats, Stage,
+};
 
 pub mod multi_async;
 
-const MAX_OPEN_FILES: usize = 10;
+pub fn find_doubles(
+    options: &Arc<ScanOptions>,
+    roots: Vec<Dir>,
+    listed: Vec<PathBuf>,
+    stats: &Arc<ScanStats>,
+) -> (Vec<DuplicateGroup>, Vec<HardLinkGroup>) {
+    let hash_options = options.hash_options;
 
-pub fn find_doubles(comp: Comparison, dir: PathBuf) -> HashMap<String, Vec<PathBuf>> {
     // Should be possible of getting rid of Rc and just use references, but it seems
     // the reference in or of the executor outlives here...
     let ex = Rc::new(LocalExecutor::new());
-    smol::block_on(ex.run(find_doubles_async(ex.clone(), comp, dir)))
+    let semaphore = Rc::new(Semaphore::new(options.max_open_files));
+
+    let start = Instant::now();
+
+    let files = smol::block_on(ex.run(find_files(
+        ex.clone(),
+        semaphore.clone(),
+        roots,
+        listed,
+        options.clone(),
+        stats.clone(),
+    )));
+
+    stats.add_timing(Phase::Walk, start.elapsed());
+
+    find_doubles_by_stages(options, files, stats, |stage, files| {
+        smol::block_on(ex.run(get_file_ids(
+            ex.clone(),
+            semaphore.clone(),
+            stage,
+            hash_options,
+            files,
+            stats.clone(),
+        )))
+    })
 }
 
-async fn find_doubles_async(
+async fn find_files(
     ex: Rc<LocalExecutor<'_>>,
-    comp: Comparison,
-    dir: PathBuf,
-) -> HashMap<String, Vec<PathBuf>> {
-    let mut files: HashMap<String, Vec<PathBuf>> = HashMap::new();
-
+    semaphore: Rc<Semaphore>,
+    roots: Vec<Dir>,
+    listed: Vec<PathBuf>,
+    options: Arc<ScanOptions>,
+    stats: Arc<ScanStats>,
+) -> Vec<FoundFile> {
     let (tx, rx) = unbounded();
+    for file in list_files(listed, &options, &stats) {
+        tx.send(file).await.unwrap();
+    }
 
-    let semaphore = Rc::new(Semaphore::new(MAX_OPEN_FILES));
-
-    enter_dir(ex, semaphore, tx, dir, comp).await;
+    for dir in roots {
+        enter_dir(
+            ex.clone(),
+            semaphore.clone(),
+            tx.clone(),
+            dir,
+            options.clone(),
+            stats.clone(),
+        )
+        .await;
+    }
+    drop(tx);
 
-    pin!(rx);
+    rx.collect().await
+}
 
-    while let Some((file_id, file_path)) = rx.next().await {
-        files.entry(file_id).or_default().push(file_path);
-    }
+async fn get_file_ids(
+    ex: Rc<LocalExecutor<'_>>,
+    semaphore: Rc<Semaphore>,
+    stage: Stage,
+    hash_options: HashOptions,
+    files: Vec<PathBuf>,
+    stats: Arc<ScanStats>,
+) -> Vec<(FileKey, PathBuf)> {
+    let (tx, rx) = unbounded();
 
-    files
+    let files: Vec<_> = files
+        .into_iter()
+        .map(|file_path| {
+            enter_file(
+                semaphore.clone(),
+                tx.clone(),
+                file_path,
+                stage,
+                hash_options,
+                stats.clone(),
+            )
+        })
+        .collect();
+    drop(tx);
+
+    let mut files_tasks = Vec::with_capacity(files.len());
+    ex.spawn_many(files, &mut files_tasks);
+    files_tasks.into_iter().for_each(Task::detach);
+
+    rx.collect().await
 }
 
 async fn enter_file(
     semaphore: Rc<Semaphore>,
-    known_names: Sender<(String, PathBuf)>,
+    known_names: Sender<(FileKey, PathBuf)>,
     file_path: PathBuf,
-    comp: Comparison,
+    stage: Stage,
+    hash_options: HashOptions,
+    stats: Arc<ScanStats>,
 ) {
     let _lock = semaphore.acquire().await;
 
-    CF.fetch_add(1, Ordering::Relaxed);
-
     /*
     if !file_path.is_file() {
         panic!("Not a file : `{}`!", file_path.to_string_lossy());
@@ -66,21 +137,11 @@ async fn enter_file(
     */
 
     // println!("file {}", file_path.to_string_lossy());
-    let file_id = match comp {
-        Comparison::FileName => get_file_id_by_file_name(&file_path).await,
-        Comparison::Hash => get_file_id_by_hash(&file_path).await,
-        Comparison::Both => get_file_id_by_both(&file_path).await,
-    };
-
-    match file_id {
+    match get_file_id(stage, hash_options, &file_path, &stats).await {
         Ok(file_id) => {
             known_names.send((file_id, file_path)).await.unwrap();
         }
-        Err(err) => eprintln!(
-            "Error when getting file identifier for `{}` : {}",
-            file_path.to_string_lossy(),
-            err
-        ),
+        Err(err) => stats.push_error(err),
     }
 
     // CF.fetch_sub(1, Ordering::Relaxed);
@@ -89,9 +150,10 @@ async fn enter_file(
 async fn enter_dir(
     ex: Rc<LocalExecutor<'_>>,
     semaphore: Rc<Semaphore>,
-    known_names: Sender<(String, PathBuf)>,
-    dir_path: PathBuf,
-    comp: Comparison,
+    known_names: Sender<FoundFile>,
+    dir: Dir,
+    options: Arc<ScanOptions>,
+    stats: Arc<ScanStats>,
 ) {
     /*
     let is_zero = format!("{:?}", semaphore)
@@ -114,60 +176,56 @@ async fn enter_dir(
     let _lock = semaphore.acquire().await;
     /*
     if !dir_path.is_dir() {
-        panic!("Not a directory : `{}`!", dir_path.to_string_lossy());
+        panic!("Not a directory : `{}`!", dir.path.to_string_lossy());
     }
     */
 
-    CD.fetch_add(1, Ordering::Relaxed);
+    stats.add_dir();
 
-    // println!("{:?} dir  {}", semaphore, dir_path.to_string_lossy());
+    // println!("{:?} dir  {}", semaphore, dir.path.to_string_lossy());
 
     let mut dirs = Vec::new();
-    let mut files = Vec::new();
-    match read_dir(&dir_path).await {
+    match read_dir(&dir.path).await {
         Ok(mut entries) => {
             while let Some(entry_res) = entries.next().await {
                 match entry_res {
                     Ok(entry) => match entry.metadata().await {
                         Ok(metadata) => {
-                            if metadata.is_dir() {
-                                dirs.push(enter_dir(
-                                    ex.clone(),
-                                    semaphore.clone(),
-                                    known_names.clone(),
-                                    entry.path(),
-                                    comp,
-                                ));
-                            } else if metadata.is_file() {
-                                files.push(enter_file(
-                                    semaphore.clone(),
-                                    known_names.clone(),
-                                    entry.path(),
-                                    comp,
-                                ));
+                            match classify(&dir, entry.path(), metadata, &options, &stats) {
+                                Ok(Entry::Dir(sub_dir)) => {
+                                    dirs.push(enter_dir(
+                                        ex.clone(),
+                                        semaphore.clone(),
+                                        known_names.clone(),
+                                        sub_dir,
+                                        options.clone(),
+                                        stats.clone(),
+                                    ));
+                                }
+                                Ok(Entry::File(file)) => {
+                                    stats.add_file();
+                                    known_names.send(file).await.unwrap();
+                                }
+                                Ok(Entry::Skipped) => stats.add_skipped(),
+                                Err(err) => stats.push_error(err),
                             }
                         }
-                        Err(err) => eprintln!(
-                            "Error when reading entry metadata `{}` : {}",
-                            entry.path().to_string_lossy(),
-                            err
-                        ),
+                        Err(err) => stats.push_error(FindDoublesError::io(
+                            &entry.path(),
+                            Operation::ReadMetadata,
+                            err,
+                        )),
                     },
-                    Err(err) => eprintln!(
-                        "Error when reading dir entry `{}` : {}",
-                        dir_path.to_string_lossy(),
-                        err
-                    ),
+                    Err(err) => stats.push_error(FindDoublesError::io(
+                        &dir.path,
+                        Operation::ReadDirEntry,
+                        err,
+                    )),
                 }
             }
         }
         Err(err) => {
-            eprintln!(
-                "{:?} Error when reading dir `{}` : {}",
-                semaphore,
-                dir_path.to_string_lossy(),
-                err
-            );
+            stats.push_error(FindDoublesError::io(&dir.path, Operation::ReadDir, err));
         }
     }
 
@@ -176,43 +234,97 @@ async fn enter_dir(
         ex.spawn_many(dirs, &mut dirs_tasks);
         dirs_tasks.into_iter().for_each(Task::detach);
     }
-    if !files.is_empty() {
-        let mut files_tasks = Vec::with_capacity(files.len());
-        ex.spawn_many(files, &mut files_tasks);
-        files_tasks.into_iter().for_each(Task::detach);
-    }
 
     // CD.fetch_sub(1, Ordering::Relaxed);
 }
 
-async fn get_file_id_by_file_name(file: &Path) -> Result<String, String> {
-    if let Some(name) = file.file_name() {
-        Ok(name.to_string_lossy().into_owned())
-    } else {
-        Err("No name for given path.".to_string())
+async fn get_file_id(
+    stage: Stage,
+    hash_options: HashOptions,
+    file: &Path,
+    stats: &ScanStats,
+) -> Result<FileKey, FindDoublesError> {
+    match stage {
+        Stage::FileName => get_file_name(file).map(FileKey::Name),
+        Stage::Head(block_size) => get_head_hash(file, block_size, hash_options, stats)
+            .await
+            .map(FileKey::Hash),
+        Stage::Tail(block_size) => get_tail_hash(file, block_size, hash_options, stats)
+            .await
+            .map(FileKey::Hash),
+        Stage::Hash => get_hash(file, hash_options, stats).await.map(FileKey::Hash),
+        Stage::Both => Ok(FileKey::Both(
+            get_file_name(file)?,
+            get_hash(file, hash_options, stats).await?,
+        )),
     }
 }
 
-async fn get_file_id_by_hash(file: &Path) -> Result<String, String> {
-    let mut hasher = Sha3_256::new();
-    let file_content = read(file).await.map_err(|e| e.to_string())?;
+async fn get_hash(
+    path: &Path,
+    hash_options: HashOptions,
+    stats: &ScanStats,
+) -> Result<Digest, FindDoublesError> {
+    let file = File::open(path)
+        .await
+        .map_err(|err| FindDoublesError::io(path, Operation::Open, err))?;
+    hash_content(file, path, hash_options, stats).await
+}
 
-    hasher.update(file_content);
+async fn get_head_hash(
+    path: &Path,
+    block_size: u64,
+    hash_options: HashOptions,
+    stats: &ScanStats,
+) -> Result<Digest, FindDoublesError> {
+    let file = File::open(path)
+        .await
+        .map_err(|err| FindDoublesError::io(path, Operation::Open, err))?;
+    hash_content(file.take(block_size), path, hash_options, stats).await
+}
 
-    let hash = hasher.finalize();
-    let mut hash_str = "0x".to_string();
-    for i in hash.iter() {
-        write!(hash_str, "{:02x}", i).unwrap();
-    }
-    Ok(hash_str)
+async fn get_tail_hash(
+    path: &Path,
+    block_size: u64,
+    hash_options: HashOptions,
+    stats: &ScanStats,
+) -> Result<Digest, FindDoublesError> {
+    let mut file = File::open(path)
+        .await
+        .map_err(|err| FindDoublesError::io(path, Operation::Open, err))?;
+    let size = file
+        .metadata()
+        .await
+        .map_err(|err| FindDoublesError::io(path, Operation::ReadMetadata, err))?
+        .len();
+    file.seek(SeekFrom::Start(size.saturating_sub(block_size)))
+        .await
+        .map_err(|err| FindDoublesError::io(path, Operation::Seek, err))?;
+    hash_content(file.take(block_size), path, hash_options, stats).await
 }
 
-async fn get_file_id_by_both(file: &Path) -> Result<String, String> {
-    let name = get_file_id_by_file_name(file).await?;
-    let hash = match get_file_id_by_hash(file).await {
-        Ok(hash) => hash,
-        Err(err) => return Err(err.to_string()),
-    };
+/// Hashes everything that can be read from `content`, one buffer at a time.
+async fn hash_content(
+    mut content: impl AsyncRead + Unpin,
+    path: &Path,
+    hash_options: HashOptions,
+    stats: &ScanStats,
+) -> Result<Digest, FindDoublesError> {
+    let mut hasher = Hasher::new(hash_options.algorithm);
+    let mut buffer = vec![0; hash_options.buffer_size.max(1)];
+
+    loop {
+        match content.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(len) => {
+                stats.add_bytes_read(len);
+                stats.add_bytes_hashed(len);
+                hasher.update(&buffer[..len]);
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(FindDoublesError::io(path, Operation::Read, err)),
+        }
+    }
 
-    Ok(format!("{}:{}", name, hash))
+    Ok(hasher.finalize())
 }
@@ -5,25 +5,33 @@ use smol::fs::read_dir;
 use smol::lock::Semaphore;
 use smol::stream::StreamExt;
 use smol::Executor;
-use smol::{pin, Task};
-use std::collections::HashMap;
+use smol::Task;
 use std::path::PathBuf;
-use std::sync::{atomic::Ordering, Arc};
+use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
 use super::{
-    get_file_id_by_both, get_file_id_by_file_name, get_file_id_by_hash, Comparison, CD, CF,
-    MAX_OPEN_FILES,
+    classify, find_doubles_by_stages, get_file_id, list_files, Dir, DuplicateGroup, Entry, FileKey,
+    FindDoublesError, FoundFile, HardLinkGroup, HashOptions, Operation, Phase, ScanOptions,
+    ScanStats, Stage,
 };
 
-pub fn find_doubles(comp: Comparison, dir: PathBuf) -> HashMap<String, Vec<PathBuf>> {
+pub fn find_doubles(
+    options: &Arc<ScanOptions>,
+    roots: Vec<Dir>,
+    listed: Vec<PathBuf>,
+    stats: &Arc<ScanStats>,
+) -> (Vec<DuplicateGroup>, Vec<HardLinkGroup>) {
+    let hash_options = options.hash_options;
+
     thread::scope(|s| {
         // TODO: remove Arc by moving executer outside ?
         let ex = Arc::new(Executor::new());
-        let num_threads = thread::available_parallelism().unwrap().into();
+        let num_threads = options.worker_count();
         let mut txs = Vec::with_capacity(num_threads);
 
-        for _ in 0..=num_threads {
+        for _ in 0..num_threads {
             let ex = ex.clone();
             let (tx, rx) = bounded(1);
             s.spawn(move || {
@@ -34,53 +42,113 @@ pub fn find_doubles(comp: Comparison, dir: PathBuf) -> HashMap<String, Vec<PathB
             txs.push(tx);
         }
 
-        let ex2 = ex.clone();
-        smol::block_on(ex.run(async {
-            let files = find_doubles_async(ex2, comp, dir).await;
+        let semaphore = Arc::new(Semaphore::new(options.max_open_files));
+
+        let start = Instant::now();
+
+        let files = smol::block_on(ex.run(find_files(
+            ex.clone(),
+            semaphore.clone(),
+            roots,
+            listed,
+            options.clone(),
+            stats.clone(),
+        )));
+
+        stats.add_timing(Phase::Walk, start.elapsed());
+
+        let results = find_doubles_by_stages(options, files, stats, |stage, files| {
+            smol::block_on(ex.run(get_file_ids(
+                ex.clone(),
+                semaphore.clone(),
+                stage,
+                hash_options,
+                files,
+                stats.clone(),
+            )))
+        });
 
+        smol::block_on(async {
             // TODO: might not need to send, just to drop, but it creates a receive err.
             for tx in txs {
                 tx.send(()).await.unwrap();
             }
+        });
 
-            files
-        }))
+        results
     })
 }
 
-async fn find_doubles_async(
+async fn find_files(
     ex: Arc<Executor<'_>>,
-    comp: Comparison,
-    dir: PathBuf,
-) -> HashMap<String, Vec<PathBuf>> {
-    let mut files: HashMap<String, Vec<PathBuf>> = HashMap::new();
-
+    semaphore: Arc<Semaphore>,
+    roots: Vec<Dir>,
+    listed: Vec<PathBuf>,
+    options: Arc<ScanOptions>,
+    stats: Arc<ScanStats>,
+) -> Vec<FoundFile> {
     let (tx, rx) = unbounded();
+    for file in list_files(listed, &options, &stats) {
+        tx.send(file).await.unwrap();
+    }
 
-    let semaphore = Arc::new(Semaphore::new(MAX_OPEN_FILES));
+    for dir in roots {
+        spawn_dir(
+            ex.clone(),
+            semaphore.clone(),
+            tx.clone(),
+            dir,
+            options.clone(),
+            stats.clone(),
+        );
+    }
+    drop(tx);
 
-    ex.spawn(enter_dir(ex.clone(), semaphore, tx, dir, comp))
-        .detach();
+    rx.collect().await
+}
 
-    pin!(rx);
+async fn get_file_ids(
+    ex: Arc<Executor<'_>>,
+    semaphore: Arc<Semaphore>,
+    stage: Stage,
+    hash_options: HashOptions,
+    files: Vec<PathBuf>,
+    stats: Arc<ScanStats>,
+) -> Vec<(FileKey, PathBuf)> {
+    let (tx, rx) = unbounded();
 
-    while let Some((file_id, file_path)) = rx.next().await {
-        files.entry(file_id).or_default().push(file_path);
-    }
+    let files: Vec<_> = files
+        .into_iter()
+        .map(|file_path| {
+            enter_file(
+                semaphore.clone(),
+                tx.clone(),
+                file_path,
+                stage,
+                hash_options,
+                stats.clone(),
+            )
+        })
+        .collect();
+    drop(tx);
+
+    let mut files_tasks = Vec::with_capacity(files.len());
+    ex.spawn_many(files, &mut files_tasks);
+    files_tasks.into_iter().for_each(Task::detach);
 
-    files
+    rx.collect().await
 }
 
 async fn enter_file(
     semaphore: Arc<Semaphore>,
-    known_names: Sender<(String, PathBuf)>,
+    known_names: Sender<(FileKey, PathBuf)>,
     file_path: PathBuf,
-    comp: Comparison,
+    stage: Stage,
+    hash_options: HashOptions,
+    stats: Arc<ScanStats>,
 ) {
     let _lock = semaphore.acquire().await;
 
-    CF.fetch_add(1, Ordering::Relaxed);
-
     /*
     if !file_path.is_file() {
         panic!("Not a file : `{}`!", file_path.to_string_lossy());
@@ -88,21 +156,11 @@ async fn enter_file(
     */
 
     // println!("file {}", file_path.to_string_lossy());
-    let file_id = match comp {
-        Comparison::FileName => get_file_id_by_file_name(&file_path).await,
-        Comparison::Hash => get_file_id_by_hash(&file_path).await,
-        Comparison::Both => get_file_id_by_both(&file_path).await,
-    };
-
-    match file_id {
+    match get_file_id(stage, hash_options, &file_path, &stats).await {
         Ok(file_id) => {
             known_names.send((file_id, file_path)).await.unwrap();
         }
-        Err(err) => eprintln!(
-            "Error when getting file identifier for `{}` : {}",
-            file_path.to_string_lossy(),
-            err
-        ),
+        Err(err) => stats.push_error(err),
     }
 
     // CF.fetch_sub(1, Ordering::Relaxed);
@@ -112,16 +170,18 @@ async fn enter_file(
 fn spawn_dir(
     ex: Arc<Executor<'_>>,
     semaphore: Arc<Semaphore>,
-    known_names: Sender<(String, PathBuf)>,
-    entry_path: PathBuf,
-    comp: Comparison,
+    known_names: Sender<FoundFile>,
+    dir: Dir,
+    options: Arc<ScanOptions>,
+    stats: Arc<ScanStats>,
 ) {
     ex.spawn(enter_dir(
         ex.clone(),
         semaphore.clone(),
         known_names.clone(),
-        entry_path,
-        comp,
+        dir,
+        options,
+        stats,
     ))
     .detach();
 }
@@ -129,9 +189,10 @@ fn spawn_dir(
 async fn enter_dir(
     ex: Arc<Executor<'_>>,
     semaphore: Arc<Semaphore>,
-    known_names: Sender<(String, PathBuf)>,
-    dir_path: PathBuf,
-    comp: Comparison,
+    known_names: Sender<FoundFile>,
+    dir: Dir,
+    options: Arc<ScanOptions>,
+    stats: Arc<ScanStats>,
 ) {
     /*
     // Affiche un message quand le sémaphore va bloquer.
@@ -153,67 +214,57 @@ async fn enter_dir(
     let _lock = semaphore.acquire().await;
     /*
     if !dir_path.is_dir() {
-        panic!("Not a directory : `{}`!", dir_path.to_string_lossy());
+        panic!("Not a directory : `{}`!", dir.path.to_string_lossy());
     }
     */
 
-    CD.fetch_add(1, Ordering::Relaxed);
+    stats.add_dir();
 
-    // println!("{:?} dir  {}", semaphore, dir_path.to_string_lossy());
+    // println!("{:?} dir  {}", semaphore, dir.path.to_string_lossy());
 
-    let mut files = Vec::new();
-    match read_dir(&dir_path).await {
+    match read_dir(&dir.path).await {
         Ok(mut entries) => {
             while let Some(entry_res) = entries.next().await {
                 match entry_res {
                     Ok(entry) => match entry.metadata().await {
                         Ok(metadata) => {
-                            if metadata.is_dir() {
-                                spawn_dir(
-                                    ex.clone(),
-                                    semaphore.clone(),
-                                    known_names.clone(),
-                                    entry.path(),
-                                    comp,
-                                );
-                            } else if metadata.is_file() {
-                                files.push(enter_file(
-                                    semaphore.clone(),
-                                    known_names.clone(),
-                                    entry.path(),
-                                    comp,
-                                ));
+                            match classify(&dir, entry.path(), metadata, &options, &stats) {
+                                Ok(Entry::Dir(sub_dir)) => {
+                                    spawn_dir(
+                                        ex.clone(),
+                                        semaphore.clone(),
+                                        known_names.clone(),
+                                        sub_dir,
+                                        options.clone(),
+                                        stats.clone(),
+                                    );
+                                }
+                                Ok(Entry::File(file)) => {
+                                    stats.add_file();
+                                    known_names.send(file).await.unwrap();
+                                }
+                                Ok(Entry::Skipped) => stats.add_skipped(),
+                                Err(err) => stats.push_error(err),
                             }
                         }
-                        Err(err) => eprintln!(
-                            "Error when reading entry metadata `{}` : {}",
-                            entry.path().to_string_lossy(),
-                            err
-                        ),
+                        Err(err) => stats.push_error(FindDoublesError::io(
+                            &entry.path(),
+                            Operation::ReadMetadata,
+                            err,
+                        )),
                     },
-                    Err(err) => eprintln!(
-                        "Error when reading dir entry `{}` : {}",
-                        dir_path.to_string_lossy(),
-                        err
-                    ),
+                    Err(err) => stats.push_error(FindDoublesError::io(
+                        &dir.path,
+                        Operation::ReadDirEntry,
+                        err,
+                    )),
                 }
             }
         }
         Err(err) => {
-            eprintln!(
-                "{:?} Error when reading dir `{}` : {}",
-                semaphore,
-                dir_path.to_string_lossy(),
-                err
-            );
+            stats.push_error(FindDoublesError::io(&dir.path, Operation::ReadDir, err));
         }
     }
 
-    if !files.is_empty() {
-        let mut files_tasks = Vec::with_capacity(files.len());
-        ex.spawn_many(files, &mut files_tasks);
-        files_tasks.into_iter().for_each(Task::detach);
-    }
-
     // CD.fetch_sub(1, Ordering::Relaxed);
 }
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// What was being done on a path when an error happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    ReadDir,
+    ReadDirEntry,
+    ReadMetadata,
+    Open,
+    Read,
+    Seek,
+    Remove,
+    Link,
+    Clone,
+    Rename,
+    Copy,
+    Write,
+    CreateDir,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::ReadDir => "reading dir",
+            Operation::ReadDirEntry => "reading dir entry",
+            Operation::ReadMetadata => "reading entry metadata",
+            Operation::Open => "opening file",
+            Operation::Read => "reading file",
+            Operation::Seek => "seeking in file",
+            Operation::Remove => "removing file",
+            Operation::Link => "linking file",
+            Operation::Clone => "cloning file",
+            Operation::Rename => "renaming file",
+            Operation::Copy => "copying file",
+            Operation::Write => "writing file",
+            Operation::CreateDir => "creating dir",
+        })
+    }
+}
+
+/// Non-fatal error met during a scan or an action on duplicates : the file or directory concerned
+/// is left out of the results, or left untouched, and the rest goes on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FindDoublesError {
+    /// The process is not allowed to access the path.
+    PermissionDenied { path: PathBuf, operation: Operation },
+    /// The path was removed or renamed since it was found.
+    Vanished { path: PathBuf, operation: Operation },
+    /// Any other input-output error.
+    Io {
+        path: PathBuf,
+        operation: Operation,
+        kind: ErrorKind,
+        message: String,
+    },
+    /// The path ends with `..` so it has no file name to compare.
+    NoFileName { path: PathBuf },
+    /// The symlink points to a path that doesn't exist.
+    BrokenSymlink { path: PathBuf },
+    /// An ignore file couldn't be read or parsed, its valid rules are still applied.
+    IgnoreFile { path: PathBuf, message: String },
+    /// The size or the content of the file changed since the scan, so it was left untouched.
+    Changed { path: PathBuf },
+    /// The duplicate is the same file as the copy kept, through another path, so it was left
+    /// untouched to not lose its content.
+    SameFile { path: PathBuf, kept: PathBuf },
+    /// The duplicate is on another device than the copy kept, so it can't be linked to it.
+    OtherDevice { path: PathBuf, kept: PathBuf },
+    /// The files of the group that could be kept are all symlinks, so the group was left untouched
+    /// to not remove the last real copy.
+    OnlySymlinks { path: PathBuf },
+    /// The entry of an action log can't be undone, as with deleted files.
+    NotUndoable { path: PathBuf, action: String },
+}
+
+impl FindDoublesError {
+    pub(crate) fn io(path: &Path, operation: Operation, err: io::Error) -> Self {
+        let path = path.to_owned();
+        match err.kind() {
+            ErrorKind::PermissionDenied => FindDoublesError::PermissionDenied { path, operation },
+            ErrorKind::NotFound => FindDoublesError::Vanished { path, operation },
+            kind => FindDoublesError::Io {
+                path,
+                operation,
+                kind,
+                message: err.to_string(),
+            },
+        }
+    }
+
+    /// Path of the file or directory concerned.
+    pub fn path(&self) -> &Path {
+        match self {
+            FindDoublesError::PermissionDenied { path, .. }
+            | FindDoublesError::Vanished { path, .. }
+            | FindDoublesError::Io { path, .. }
+            | FindDoublesError::IgnoreFile { path, .. }
+            | FindDoublesError::SameFile { path, .. }
+            | FindDoublesError::OtherDevice { path, .. }
+            | FindDoublesError::OnlySymlinks { path }
+            | FindDoublesError::NotUndoable { path, .. }
+            | FindDoublesError::Changed { path }
+            | FindDoublesError::NoFileName { path }
+            | FindDoublesError::BrokenSymlink { path } => path,
+        }
+    }
+}
+
+impl Display for FindDoublesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FindDoublesError::PermissionDenied { path, operation } => write!(
+                f,
+                "Error when {} `{}` : permission denied",
+                operation,
+                path.to_string_lossy()
+            ),
+            FindDoublesError::Vanished { path, operation } => write!(
+                f,
+                "Error when {} `{}` : it does not exist anymore",
+                operation,
+                path.to_string_lossy()
+            ),
+            FindDoublesError::Io {
+                path,
+                operation,
+                message,
+                ..
+            } => write!(
+                f,
+                "Error when {} `{}` : {}",
+                operation,
+                path.to_string_lossy(),
+                message
+            ),
+            FindDoublesError::NoFileName { path } => write!(
+                f,
+                "Error when getting file name of `{}` : no name for given path",
+                path.to_string_lossy()
+            ),
+            FindDoublesError::BrokenSymlink { path } => write!(
+                f,
+                "Error when following symlink `{}` : its target does not exist",
+                path.to_string_lossy()
+            ),
+            FindDoublesError::IgnoreFile { path, message } => write!(
+                f,
+                "Error when reading ignore file `{}` : {}",
+                path.to_string_lossy(),
+                message
+            ),
+            FindDoublesError::Changed { path } => write!(
+                f,
+                "Error when checking file `{}` : it changed since the scan, leaving it untouched",
+                path.to_string_lossy()
+            ),
+            FindDoublesError::SameFile { path, kept } => write!(
+                f,
+                "Error when checking file `{}` : it is the same file as the kept `{}`, leaving it untouched",
+                path.to_string_lossy(),
+                kept.to_string_lossy()
+            ),
+            FindDoublesError::OtherDevice { path, kept } => write!(
+                f,
+                "Error when linking file `{}` : it is on another device than the kept `{}`, leaving it untouched",
+                path.to_string_lossy(),
+                kept.to_string_lossy()
+            ),
+            FindDoublesError::OnlySymlinks { path } => write!(
+                f,
+                "Error when choosing the copy to keep with `{}` : only symlinks could be kept, leaving the group untouched",
+                path.to_string_lossy()
+            ),
+            FindDoublesError::NotUndoable { path, action } => write!(
+                f,
+                "Error when undoing `{}` : the `{}` action can't be undone",
+                path.to_string_lossy(),
+                action
+            ),
+        }
+    }
+}
+
+impl Error for FindDoublesError {}
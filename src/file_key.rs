@@ -0,0 +1,57 @@
+use std::ffi::OsString;
+use std::fmt::{self, Display};
+
+/// Maximum length in bytes of the digests of the available hash algorithms.
+pub const DIGEST_MAX_LEN: usize = 32;
+
+/// Result of the hash of a file content, stored inline so it can be used as a key without any
+/// allocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Digest {
+    bytes: [u8; DIGEST_MAX_LEN],
+    len: u8,
+}
+
+impl Digest {
+    /// Panics if `hash` is longer than [`DIGEST_MAX_LEN`].
+    pub fn new(hash: &[u8]) -> Self {
+        let mut bytes = [0; DIGEST_MAX_LEN];
+        bytes[..hash.len()].copy_from_slice(hash);
+        Digest {
+            bytes,
+            len: hash.len() as u8,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("0x")?;
+        for i in self.as_bytes() {
+            write!(f, "{:02x}", i)?;
+        }
+        Ok(())
+    }
+}
+
+/// What files are compared on to find duplicates.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FileKey {
+    Name(OsString),
+    Hash(Digest),
+    Both(OsString, Digest),
+}
+
+impl Display for FileKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileKey::Name(name) => write!(f, "{}", name.to_string_lossy()),
+            FileKey::Hash(digest) => write!(f, "{}", digest),
+            FileKey::Both(name, digest) => write!(f, "{}:{}", name.to_string_lossy(), digest),
+        }
+    }
+}
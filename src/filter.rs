@@ -0,0 +1,74 @@
+extern crate globset;
+extern crate regex;
+
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use std::path::Path;
+
+/// Pattern matched against the paths found while walking.
+#[derive(Clone, Debug)]
+pub struct Pattern(PatternKind);
+
+#[derive(Clone, Debug)]
+enum PatternKind {
+    /// Glob without `/`, matched against the name of the entry.
+    Name(GlobMatcher),
+    /// Glob with a `/`, matched against the whole path of the entry.
+    Path(GlobMatcher),
+    /// Regular expression searched in the whole path of the entry.
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Shell-like pattern such as `*.jpg` or `**/target`. Without `/`, it is matched against the
+    /// name of files and directories, otherwise against their whole path.
+    pub fn glob(pattern: &str) -> Result<Self, String> {
+        let matcher = Glob::new(pattern)
+            .map_err(|err| format!("Could not parse `{}` as glob : {}", pattern, err))?
+            .compile_matcher();
+
+        Ok(Pattern(if pattern.contains('/') {
+            PatternKind::Path(matcher)
+        } else {
+            PatternKind::Name(matcher)
+        }))
+    }
+
+    /// Regular expression searched anywhere in the whole path of files and directories, use `^`
+    /// and `$` to anchor it.
+    pub fn regex(pattern: &str) -> Result<Self, String> {
+        Regex::new(pattern)
+            .map(|regex| Pattern(PatternKind::Regex(regex)))
+            .map_err(|err| format!("Could not parse `{}` as regex : {}", pattern, err))
+    }
+
+    pub(crate) fn matches(&self, path: &Path) -> bool {
+        match &self.0 {
+            PatternKind::Name(glob) => path.file_name().is_some_and(|name| glob.is_match(name)),
+            PatternKind::Path(glob) => glob.is_match(path),
+            PatternKind::Regex(regex) => regex.is_match(&path.to_string_lossy()),
+        }
+    }
+}
+
+/// Include and exclude patterns shared by all the walkers.
+///
+/// An entry matching any exclude pattern is left out, and an excluded directory isn't entered.
+/// When there are include patterns, only the files matching one of them are scanned, while
+/// directories are still entered to look for such files.
+#[derive(Clone, Debug, Default)]
+pub struct Filters {
+    pub(crate) include: Vec<Pattern>,
+    pub(crate) exclude: Vec<Pattern>,
+}
+
+impl Filters {
+    pub(crate) fn accepts_dir(&self, path: &Path) -> bool {
+        !self.exclude.iter().any(|pattern| pattern.matches(path))
+    }
+
+    pub(crate) fn accepts_file(&self, path: &Path) -> bool {
+        self.accepts_dir(path)
+            && (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(path)))
+    }
+}
@@ -0,0 +1,48 @@
+extern crate blake3;
+extern crate sha2;
+extern crate xxhash_rust;
+
+use sha2::Sha256;
+use sha3::{Digest, Sha3_256};
+use xxhash_rust::xxh3::Xxh3;
+
+use super::{Digest as FileDigest, HashAlgorithm};
+
+/// State of a hash computation, whatever the selected algorithm.
+pub enum Hasher {
+    Sha3_256(Box<Sha3_256>),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha3_256 => Hasher::Sha3_256(Box::new(Sha3_256::new())),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha3_256(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> FileDigest {
+        match self {
+            Hasher::Sha3_256(hasher) => FileDigest::new(&hasher.finalize()),
+            Hasher::Sha256(hasher) => FileDigest::new(&hasher.finalize()),
+            Hasher::Blake3(hasher) => FileDigest::new(hasher.finalize().as_bytes()),
+            Hasher::Xxh3(hasher) => FileDigest::new(&hasher.digest128().to_be_bytes()),
+        }
+    }
+}
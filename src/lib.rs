@@ -1,24 +1,49 @@
-#![feature(async_closure)]
 extern crate sha3;
 
 use std::collections::HashMap;
-use std::fmt::{Display, Write};
-use std::fs::read;
+use std::ffi::OsString;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
-use sha3::{Digest, Sha3_256};
-
+mod action;
+mod action_log;
 mod async_version;
+mod error;
+mod file_key;
+mod filter;
+mod hasher;
+mod list;
 mod sync;
+pub use action::{
+    apply_action, Action, ActionOptions, ActionReport, ActionStep, KeepRule, DEFAULT_QUARANTINE,
+    DEFAULT_SYMLINK_LOG, QUARANTINE_MANIFEST,
+};
+pub use action_log::{read_action_log, undo_action_log, LogEntry, UndoReport};
 use async_version::multi_async;
+pub use error::{FindDoublesError, Operation};
+pub use file_key::{Digest, FileKey, DIGEST_MAX_LEN};
+pub use filter::Pattern;
+use hasher::Hasher;
+pub use list::read_file_list;
+pub use options::{OutputSink, PathFilter, ScanOptions, SymlinkPolicy, DEFAULT_MAX_OPEN_FILES};
+pub use report::{DuplicateFile, DuplicateGroup, DuplicateReport, HardLinkGroup, Mismatch};
+pub use stats::{Phase, ScanStats};
 mod multithreaded;
+mod options;
+mod reflink;
+mod report;
+mod stats;
+#[cfg(test)]
+mod test_dir;
 mod threaded;
-
-static CF: AtomicIsize = const { AtomicIsize::new(0) };
-static CD: AtomicIsize = const { AtomicIsize::new(0) };
+mod verify;
+mod walk;
+use walk::FoundFile;
 
 pub const COMP_NAME: &str = "name";
 pub const COMP_HASH: &str = "hash";
@@ -29,6 +54,27 @@ pub const BACK_MULTI_ASYNC: &str = "multi_async";
 pub const BACK_THREADED: &str = "thread";
 pub const BACK_MULTI_THREADED: &str = "multi_thread";
 pub const BACK_ALL: &str = "all";
+pub const HASH_SHA3_256: &str = "sha3-256";
+pub const HASH_SHA256: &str = "sha256";
+pub const HASH_BLAKE3: &str = "blake3";
+pub const HASH_XXH3: &str = "xxh3";
+pub const SYMLINK_IGNORE: &str = "ignore";
+pub const SYMLINK_FOLLOW: &str = "follow";
+pub const SYMLINK_REPORT: &str = "report";
+pub const ACTION_DELETE: &str = "delete";
+pub const ACTION_HARD_LINK: &str = "hard-link";
+pub const ACTION_REFLINK: &str = "reflink";
+pub const ACTION_SYMLINK: &str = "symlink";
+pub const ACTION_MOVE_TO: &str = "move-to";
+pub const KEEP_FIRST_ROOT: &str = "first-root";
+pub const KEEP_OLDEST: &str = "oldest";
+pub const KEEP_NEWEST: &str = "newest";
+pub const KEEP_SHORTEST_PATH: &str = "shortest-path";
+
+/// Default size of the blocks hashed by [`HashOptions`].
+pub const DEFAULT_BLOCK_SIZE: u64 = 16 * 1024;
+/// Default size of the buffer used to read files content by [`HashOptions`].
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Comparison {
@@ -41,10 +87,10 @@ impl FromStr for Comparison {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (comp, msg) = match s.to_lowercase().as_ref() {
-            COMP_NAME => (Comparison::FileName, "name"),
-            COMP_HASH => (Comparison::Hash, "SHA3-256 hash"),
-            COMP_BOTH => (Comparison::Both, "name and SHA3-256 hash"),
+        let comp = match s.to_lowercase().as_ref() {
+            COMP_NAME => Comparison::FileName,
+            COMP_HASH => Comparison::Hash,
+            COMP_BOTH => Comparison::Both,
             _ => {
                 return Err(format!(
                     "Could not parse `{}` as comparison method, please use `{}`, `{}`, or `{}`.",
@@ -53,20 +99,118 @@ impl FromStr for Comparison {
             }
         };
 
-        eprintln!("Using file {} to compare files and detect duplicates.", msg);
-
         Ok(comp)
     }
 }
 
-#[derive(Debug)]
+/// Algorithm used to hash files content.
+///
+/// Cryptographic hashes make accidental collisions practically impossible, but XXH3 (128 bits) is
+/// much faster when throughput matters more than collision resistance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha3_256,
+    Sha256,
+    Blake3,
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha3_256 => "SHA3-256",
+            HashAlgorithm::Sha256 => "SHA-256",
+            HashAlgorithm::Blake3 => "BLAKE3",
+            HashAlgorithm::Xxh3 => "XXH3-128",
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let algorithm = match s.to_lowercase().as_ref() {
+            HASH_SHA3_256 => HashAlgorithm::Sha3_256,
+            HASH_SHA256 => HashAlgorithm::Sha256,
+            HASH_BLAKE3 => HashAlgorithm::Blake3,
+            HASH_XXH3 => HashAlgorithm::Xxh3,
+            _ => {
+                return Err(format!(
+                    "Could not parse `{}` as hash algorithm, please use `{}`, `{}`, `{}`, or `{}`.",
+                    s, HASH_SHA3_256, HASH_SHA256, HASH_BLAKE3, HASH_XXH3
+                ));
+            }
+        };
+
+        Ok(algorithm)
+    }
+}
+
+/// How files content is hashed.
+///
+/// Before hashing the whole content of files sharing the same size, only a block at their
+/// beginning and then at their end are hashed, so files that differ early don't have to be read
+/// entirely. Block sizes are in bytes, a size of `0` disables the corresponding stage.
+///
+/// Files are read through a buffer of `buffer_size` bytes, so memory usage doesn't depend on the
+/// size of the files.
+#[derive(Clone, Copy, Debug)]
+pub struct HashOptions {
+    pub algorithm: HashAlgorithm,
+    pub head_size: u64,
+    pub tail_size: u64,
+    pub buffer_size: usize,
+}
+
+impl Default for HashOptions {
+    fn default() -> Self {
+        HashOptions {
+            algorithm: HashAlgorithm::default(),
+            head_size: DEFAULT_BLOCK_SIZE,
+            tail_size: DEFAULT_BLOCK_SIZE,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+}
+
+/// What identifier is computed for each file at a given step of the comparison.
+#[derive(Clone, Copy, Debug)]
+enum Stage {
+    FileName,
+    /// Hash of the first bytes of the file.
+    Head(u64),
+    /// Hash of the last bytes of the file.
+    Tail(u64),
+    Hash,
+    Both,
+}
+
+#[derive(Clone, Copy, Debug)]
 pub enum Backend {
     Sync,
     Async,
     MultiAsync,
     Threaded,
     MultiThreaded,
-    All,
+}
+
+impl Backend {
+    /// Every available backend, to compare them.
+    pub const ALL: [Backend; 5] = [
+        Backend::Sync,
+        Backend::Async,
+        Backend::Threaded,
+        Backend::MultiThreaded,
+        Backend::MultiAsync,
+    ];
 }
 
 impl FromStr for Backend {
@@ -79,7 +223,6 @@ impl FromStr for Backend {
             BACK_MULTI_ASYNC => Backend::MultiAsync,
             BACK_THREADED => Backend::Threaded,
             BACK_MULTI_THREADED => Backend::MultiThreaded,
-            BACK_ALL => Backend::All,
             _ => {
                 return Err(format!(
                     "Could not parse `{}` as backend, please use `{}`, `{}`, `{}`, `{}`, or `{}`.",
@@ -88,96 +231,484 @@ impl FromStr for Backend {
             }
         };
 
-        eprintln!("Using backend {}.", s);
-
         Ok(comp)
     }
 }
 
-pub fn find_doubles(enable_output: bool, comp: Comparison, backend: Backend, dir: PathBuf) {
-    if let Backend::All = backend {
-        eprintln!("Useless first try that should be much longer if the system hasn't already cached files.");
-        find_doubles(enable_output, comp, Backend::MultiThreaded, dir.clone());
-        eprintln!("--------------------------------------------------------------------------------------------------------------------------------\n");
-        find_doubles(enable_output, comp, Backend::Sync, dir.clone());
-        find_doubles(enable_output, comp, Backend::Async, dir.clone());
-        find_doubles(enable_output, comp, Backend::Threaded, dir.clone());
-        find_doubles(enable_output, comp, Backend::MultiThreaded, dir.clone());
-        find_doubles(enable_output, comp, Backend::MultiAsync, dir.clone());
-        return;
-    }
+/// Looks for duplicate files across all the `roots` as configured by `options` and returns them
+/// without printing anything.
+///
+/// Each file of the report is tagged with the index of its root in `roots`, followed by the
+/// reference directories of `options`. Roots nested in another one are walked on their own, so
+/// their files are only reported once.
+pub fn find_doubles(roots: &[PathBuf], options: &ScanOptions) -> DuplicateReport {
+    scan(roots.to_vec(), Vec::new(), options)
+}
 
-    eprintln!("Backend {:?}", backend);
+/// Looks for duplicate files among the listed `files` instead of walking directories, see
+/// [`read_file_list`], as configured by `options`.
+///
+/// The listed files are tagged with a first root with an empty path, as their paths are used as
+/// they were listed. Only the reference directories of `options` are walked.
+pub fn find_listed_doubles(files: Vec<PathBuf>, options: &ScanOptions) -> DuplicateReport {
+    scan(vec![PathBuf::new()], files, options)
+}
 
-    let backend = match backend {
+fn scan(roots: Vec<PathBuf>, listed: Vec<PathBuf>, options: &ScanOptions) -> DuplicateReport {
+    let backend_fn: fn(&Arc<ScanOptions>, _, _, &Arc<ScanStats>) -> _ = match options.backend {
         Backend::Sync => sync::find_doubles,
         Backend::Async => async_version::find_doubles,
         Backend::MultiAsync => multi_async::find_doubles,
         Backend::Threaded => threaded::find_doubles,
         Backend::MultiThreaded => multithreaded::find_doubles,
-        Backend::All => unreachable!(),
     };
 
-    // Reset file and directory counters.
-    CF.store(0, Ordering::Relaxed);
-    CD.store(0, Ordering::Relaxed);
+    let options = Arc::new(options.clone());
+    let stats = Arc::new(ScanStats::default());
+    let comp = options.comparison;
 
-    let start = Instant::now();
-    let files = backend(comp, dir);
-    let end = Instant::now();
+    let first_reference = roots.len();
+    let roots: Vec<_> = roots
+        .into_iter()
+        .chain(options.references.clone())
+        .collect();
+    let dirs = walk::roots(&roots, first_reference, &options, &stats);
+    let (mut groups, hard_links) = backend_fn(&options, dirs, listed, &stats);
+    let mut mismatches = Vec::new();
+    if options.verify && !matches!(comp, Comparison::FileName) {
+        let start = Instant::now();
+        (groups, mismatches) =
+            verify::verify_doubles(groups, options.hash_options.buffer_size, &stats);
+        stats.add_timing(Phase::Verify, start.elapsed());
+        groups.retain(|group| {
+            let references = group.files.iter().filter(|file| file.reference).count();
+            keeps_group(&options, group.files.len(), references)
+        });
+    }
+
+    let errors = stats.take_errors();
+    if let Some(output) = &options.output {
+        errors.iter().for_each(|err| output.error(err));
+        groups.iter().for_each(|group| output.group(group));
+        hard_links.iter().for_each(|group| output.hard_links(group));
+    }
 
-    if enable_output {
-        display_doubles(&files);
+    DuplicateReport {
+        comparison: comp,
+        hash_algorithm: match comp {
+            Comparison::FileName => None,
+            Comparison::Hash | Comparison::Both => Some(options.hash_options.algorithm),
+        },
+        backend: options.backend,
+        roots,
+        groups,
+        mismatches,
+        hard_links,
+        errors,
+        stats: stats.as_ref().clone(),
     }
+}
+
+/// Groups the files found by a backend and only computes the identifiers of the files that can
+/// still be duplicates, using `get_file_ids` to compute those of a list of files with the backend
+/// method.
+///
+/// When comparing hashes, files are first grouped by size, then by the hash of their first and
+/// last blocks (see [`HashOptions`]), and only then by the hash of their whole content. After each
+/// step, groups of only one file are dropped since it can't have any duplicate. Hard links to the
+/// same file are only hashed once, through the first of their paths, and returned on their own.
+///
+/// With reference directories, groups without both reference and candidate files are dropped too.
+fn find_doubles_by_stages<F>(
+    options: &ScanOptions,
+    files: impl IntoIterator<Item = FoundFile>,
+    stats: &ScanStats,
+    mut get_file_ids: F,
+) -> (Vec<DuplicateGroup>, Vec<HardLinkGroup>)
+where
+    F: FnMut(Stage, Vec<PathBuf>) -> Vec<(FileKey, PathBuf)>,
+{
+    let (comp, hash_options) = (options.comparison, options.hash_options);
+    let last_stage = match comp {
+        Comparison::FileName => Stage::FileName,
+        Comparison::Hash => Stage::Hash,
+        Comparison::Both => Stage::Both,
+    };
+    let (mut groups, hard_links) = match comp {
+        Comparison::FileName => (vec![files.into_iter().collect()], Vec::new()),
+        Comparison::Hash | Comparison::Both => {
+            let (files, hard_links) = split_hard_links(files);
+            (same_size_files(options, files), hard_links)
+        }
+    };
+
+    if !matches!(comp, Comparison::FileName) {
+        for (block_size, stage) in [
+            (hash_options.head_size, Stage::Head(hash_options.head_size)),
+            (hash_options.tail_size, Stage::Tail(hash_options.tail_size)),
+        ] {
+            if block_size == 0 {
+                continue;
+            }
+
+            // If the block covers the whole file, it is better to directly hash all of it.
+            let (to_split, mut next_groups): (Vec<_>, Vec<_>) = groups
+                .into_iter()
+                .partition(|group: &Vec<FoundFile>| group[0].size > block_size);
 
-    eprintln!(
-        "    Stats : files {}, dirs {}",
-        CF.load(Ordering::Acquire),
-        CD.load(Ordering::Acquire)
+            next_groups.extend(
+                split_groups(options, to_split, stage, stats, &mut get_file_ids)
+                    .into_iter()
+                    .map(|(_, group)| group),
+            );
+            groups = next_groups;
+        }
+    }
+
+    let groups = split_groups(options, groups, last_stage, stats, &mut get_file_ids)
+        .into_iter()
+        .map(|(key, group)| DuplicateGroup {
+            key,
+            size: group.iter().map(|file| file.size).max().unwrap_or(0),
+            files: group.into_iter().map(DuplicateFile::from).collect(),
+        })
+        .collect();
+
+    (groups, hard_links)
+}
+
+impl From<FoundFile> for DuplicateFile {
+    fn from(file: FoundFile) -> Self {
+        DuplicateFile {
+            path: file.path,
+            root: file.root,
+            reference: file.reference,
+            symlink: file.symlink,
+        }
+    }
+}
+
+/// Keeps only one path of the files having several hard links, so their content is read once, and
+/// groups the paths of each of them. The kept path is in a reference directory if one of them is.
+fn split_hard_links(
+    files: impl IntoIterator<Item = FoundFile>,
+) -> (Vec<FoundFile>, Vec<HardLinkGroup>) {
+    let mut unique_files = Vec::new();
+    let mut links: HashMap<(u64, u64), Vec<FoundFile>> = HashMap::new();
+    for file in files {
+        match file.id {
+            Some(id) => links.entry(id).or_default().push(file),
+            None => unique_files.push(file),
+        }
+    }
+
+    let mut hard_links = Vec::new();
+    for mut paths in links.into_values() {
+        // Keeps the same path whatever the order in which the backend found them.
+        paths.sort_unstable_by(|a, b| (!a.reference, &a.path).cmp(&(!b.reference, &b.path)));
+        unique_files.push(paths[0].clone());
+
+        if paths.len() > 1 {
+            hard_links.push(HardLinkGroup {
+                size: paths[0].size,
+                files: paths.into_iter().map(DuplicateFile::from).collect(),
+            });
+        }
+    }
+
+    (unique_files, hard_links)
+}
+
+/// Splits each group of files by their identifiers computed for `stage`, only keeping the
+/// resulting groups that can still hold duplicates.
+fn split_groups<F>(
+    options: &ScanOptions,
+    groups: Vec<Vec<FoundFile>>,
+    stage: Stage,
+    stats: &ScanStats,
+    get_file_ids: &mut F,
+) -> Vec<(FileKey, Vec<FoundFile>)>
+where
+    F: FnMut(Stage, Vec<PathBuf>) -> Vec<(FileKey, PathBuf)>,
+{
+    if groups.is_empty() {
+        return Vec::new();
+    }
+
+    let mut group_of_file = HashMap::new();
+    for (i, group) in groups.into_iter().enumerate() {
+        for file in group {
+            group_of_file.insert(file.path.clone(), (i, file));
+        }
+    }
+
+    let start = Instant::now();
+    let file_ids = get_file_ids(stage, group_of_file.keys().cloned().collect());
+    stats.add_timing(
+        match stage {
+            Stage::Head(_) => Phase::HeadHash,
+            Stage::Tail(_) => Phase::TailHash,
+            Stage::FileName | Stage::Hash | Stage::Both => Phase::Identify,
+        },
+        start.elapsed(),
     );
 
-    eprintln!("    Finished in {}s\n", end.duration_since(start).as_secs());
+    let mut split_groups: HashMap<(usize, FileKey), Vec<FoundFile>> = HashMap::new();
+    for (file_id, file_path) in file_ids {
+        if let Some((i, file)) = group_of_file.remove(&file_path) {
+            split_groups.entry((i, file_id)).or_default().push(file);
+        }
+    }
+
+    split_groups
+        .into_iter()
+        .filter(|(_, group)| keeps_found_files(options, group))
+        .map(|((_, file_id), group)| (file_id, group))
+        .collect()
 }
 
-fn display_doubles<String: Display>(files: &HashMap<String, Vec<PathBuf>>) {
-    files
-        .iter()
-        .filter(|(_, vec)| vec.len() > 1)
-        .for_each(|(f, vec)| {
-            println!("{} :", f);
-            vec.iter()
-                .for_each(|path| println!("    - {}", path.to_string_lossy()));
-        });
+/// Groups files by size, only keeping the groups that can hold duplicates : a file with a unique
+/// size can't have any duplicate, so there is no need to compute its hash.
+fn same_size_files(
+    options: &ScanOptions,
+    files: impl IntoIterator<Item = FoundFile>,
+) -> Vec<Vec<FoundFile>> {
+    let mut sizes: HashMap<u64, Vec<FoundFile>> = HashMap::new();
+    for file in files {
+        sizes.entry(file.size).or_default().push(file);
+    }
+
+    sizes
+        .into_values()
+        .filter(|vec| keeps_found_files(options, vec))
+        .collect()
 }
 
-fn get_file_id_by_file_name(file: &Path) -> Result<String, String> {
+fn keeps_found_files(options: &ScanOptions, files: &[FoundFile]) -> bool {
+    let references = files.iter().filter(|file| file.reference).count();
+    keeps_group(options, files.len(), references)
+}
+
+/// Whether a group of `len` files, `references` of which are in reference directories, can hold
+/// duplicates to report : it needs two files, and both reference and candidate files when there
+/// are reference directories.
+fn keeps_group(options: &ScanOptions, len: usize, references: usize) -> bool {
+    len > 1 && (options.references.is_empty() || (references > 0 && references < len))
+}
+
+fn get_file_id(
+    stage: Stage,
+    hash_options: HashOptions,
+    file: &Path,
+    stats: &ScanStats,
+) -> Result<FileKey, FindDoublesError> {
+    match stage {
+        Stage::FileName => get_file_name(file).map(FileKey::Name),
+        Stage::Head(block_size) => {
+            get_head_hash(file, block_size, hash_options, stats).map(FileKey::Hash)
+        }
+        Stage::Tail(block_size) => {
+            get_tail_hash(file, block_size, hash_options, stats).map(FileKey::Hash)
+        }
+        Stage::Hash => get_hash(file, hash_options, stats).map(FileKey::Hash),
+        Stage::Both => Ok(FileKey::Both(
+            get_file_name(file)?,
+            get_hash(file, hash_options, stats)?,
+        )),
+    }
+}
+
+fn get_file_name(file: &Path) -> Result<OsString, FindDoublesError> {
     if let Some(name) = file.file_name() {
-        Ok(name.to_string_lossy().into_owned())
+        Ok(name.to_owned())
     } else {
-        Err("No name for given path.".to_string())
+        Err(FindDoublesError::NoFileName {
+            path: file.to_owned(),
+        })
     }
 }
 
-fn get_file_id_by_hash(file: &Path) -> Result<String, String> {
-    let mut hasher = Sha3_256::new();
-    let file_content = read(file).map_err(|e| e.to_string())?;
+fn get_hash(
+    path: &Path,
+    hash_options: HashOptions,
+    stats: &ScanStats,
+) -> Result<Digest, FindDoublesError> {
+    let file = File::open(path).map_err(|err| FindDoublesError::io(path, Operation::Open, err))?;
+    hash_content(file, path, hash_options, stats)
+}
+
+fn get_head_hash(
+    path: &Path,
+    block_size: u64,
+    hash_options: HashOptions,
+    stats: &ScanStats,
+) -> Result<Digest, FindDoublesError> {
+    let file = File::open(path).map_err(|err| FindDoublesError::io(path, Operation::Open, err))?;
+    hash_content(file.take(block_size), path, hash_options, stats)
+}
+
+fn get_tail_hash(
+    path: &Path,
+    block_size: u64,
+    hash_options: HashOptions,
+    stats: &ScanStats,
+) -> Result<Digest, FindDoublesError> {
+    let mut file =
+        File::open(path).map_err(|err| FindDoublesError::io(path, Operation::Open, err))?;
+    let size = file
+        .metadata()
+        .map_err(|err| FindDoublesError::io(path, Operation::ReadMetadata, err))?
+        .len();
+    file.seek(SeekFrom::Start(size.saturating_sub(block_size)))
+        .map_err(|err| FindDoublesError::io(path, Operation::Seek, err))?;
+    hash_content(file.take(block_size), path, hash_options, stats)
+}
 
-    hasher.update(file_content);
+/// Hashes everything that can be read from `content`, one buffer at a time.
+fn hash_content(
+    mut content: impl Read,
+    path: &Path,
+    hash_options: HashOptions,
+    stats: &ScanStats,
+) -> Result<Digest, FindDoublesError> {
+    let mut hasher = Hasher::new(hash_options.algorithm);
+    let mut buffer = vec![0; hash_options.buffer_size.max(1)];
 
-    let hash = hasher.finalize();
-    let mut hash_str = "0x".to_string();
-    for i in hash.iter() {
-        write!(hash_str, "{:02x}", i).unwrap();
+    loop {
+        match content.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => {
+                stats.add_bytes_read(len);
+                stats.add_bytes_hashed(len);
+                hasher.update(&buffer[..len]);
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(FindDoublesError::io(path, Operation::Read, err)),
+        }
     }
-    Ok(hash_str)
+
+    Ok(hasher.finalize())
 }
 
-fn get_file_id_by_both(file: &Path) -> Result<String, String> {
-    let name = get_file_id_by_file_name(file)?;
-    let hash = match get_file_id_by_hash(file) {
-        Ok(hash) => hash,
-        Err(err) => return Err(err.to_string()),
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn empty_files_are_only_left_out_when_comparing_content() {
+        let dir = TestDir::new();
+        dir.write("a/empty", "");
+        dir.write("b/empty", "");
+        let scan = |options: ScanOptions| find_doubles(&[dir.path().to_owned()], &options);
 
-    Ok(format!("{}:{}", name, hash))
+        assert_eq!(scan(ScanOptions::new()).groups.len(), 1);
+        let hash = ScanOptions::new().comparison(Comparison::Hash);
+        assert!(scan(hash.clone()).groups.is_empty());
+        assert_eq!(scan(hash.min_size(0)).groups.len(), 1);
+    }
+
+    #[test]
+    fn every_backend_finds_the_same_groups() {
+        let dir = TestDir::new();
+        // Same size files differing only in their head, tail or middle, each written twice.
+        for (name, content) in [
+            ("same", "aaaabbbbcccc"),
+            ("head", "Xaaabbbbcccc"),
+            ("tail", "aaaabbbbcccX"),
+            ("middle", "aaaabXbbcccc"),
+        ] {
+            dir.write(&format!("one/{name}"), content);
+            dir.write(&format!("two/{name}"), content);
+        }
+        let hash_options = HashOptions {
+            head_size: 4,
+            tail_size: 4,
+            ..HashOptions::default()
+        };
+
+        let mut expected: Vec<_> = ["same", "head", "tail", "middle"]
+            .map(|name| {
+                vec![
+                    dir.path().join("one").join(name),
+                    dir.path().join("two").join(name),
+                ]
+            })
+            .into();
+        expected.sort();
+        for backend in Backend::ALL {
+            let options = ScanOptions::new()
+                .comparison(Comparison::Hash)
+                .hash_options(hash_options)
+                .backend(backend);
+            let report = find_doubles(&[dir.path().to_owned()], &options);
+
+            let mut groups: Vec<_> = report
+                .groups
+                .iter()
+                .map(|group| {
+                    let mut paths: Vec<_> =
+                        group.files.iter().map(|file| file.path.clone()).collect();
+                    paths.sort();
+                    paths
+                })
+                .collect();
+            groups.sort();
+            assert_eq!(groups, expected, "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn unique_sizes_are_never_read() {
+        let dir = TestDir::new();
+        dir.write("a", "a");
+        dir.write("b", "bb");
+        dir.write("sub/c", "ccc");
+
+        for backend in Backend::ALL {
+            let options = ScanOptions::new()
+                .comparison(Comparison::Hash)
+                .backend(backend);
+            let report = find_doubles(&[dir.path().to_owned()], &options);
+
+            assert!(report.groups.is_empty());
+            assert_eq!(report.stats.files(), 3, "{backend:?}");
+            assert_eq!(report.stats.bytes_read(), 0, "{backend:?}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_not_hard_links() {
+        let dir = TestDir::new();
+        let a = dir.write("a", "hello");
+        let b = dir.write("b", "hello");
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("a", &link).unwrap();
+
+        for symlinks in [SymlinkPolicy::Follow, SymlinkPolicy::Report] {
+            let options = ScanOptions::new()
+                .comparison(Comparison::Hash)
+                .symlinks(symlinks);
+            let report = find_doubles(&[dir.path().to_owned()], &options);
+
+            assert!(report.hard_links.is_empty());
+            assert_eq!(report.groups.len(), 1);
+            assert_eq!(report.reclaimable_bytes(), 5);
+            let symlinks: Vec<_> = report.groups[0]
+                .files
+                .iter()
+                .filter(|file| file.symlink)
+                .map(|file| &file.path)
+                .collect();
+            assert_eq!(symlinks, [&link]);
+            let mut paths: Vec<_> = report.groups[0]
+                .files
+                .iter()
+                .map(|file| &file.path)
+                .collect();
+            paths.sort();
+            assert_eq!(paths, [&a, &b, &link]);
+        }
+    }
 }
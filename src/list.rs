@@ -0,0 +1,58 @@
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+
+/// Reads a list of paths separated by `separator`, usually `b'\n'`, or `b'\0'` for the output of
+/// commands such as `find -print0` or `git ls-files -z`, which allows any character in paths.
+///
+/// Empty entries are skipped, and with newlines a `\r` ending an entry is removed.
+pub fn read_file_list(mut reader: impl BufRead, separator: u8) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut entry = Vec::new();
+
+    while reader.read_until(separator, &mut entry)? > 0 {
+        if entry.last() == Some(&separator) {
+            entry.pop();
+        }
+        if separator == b'\n' && entry.last() == Some(&b'\r') {
+            entry.pop();
+        }
+        if !entry.is_empty() {
+            paths.push(path_from_bytes(std::mem::take(&mut entry)));
+        }
+        entry.clear();
+    }
+
+    Ok(paths)
+}
+
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_newline_separated_paths() {
+        let list = "a\r\n\nb c\nd/e";
+        let paths = read_file_list(list.as_bytes(), b'\n').unwrap();
+        assert_eq!(paths, ["a", "b c", "d/e"].map(PathBuf::from));
+    }
+
+    #[test]
+    fn reads_nul_separated_paths() {
+        let list = "a\nb\0\0c\r\0";
+        let paths = read_file_list(list.as_bytes(), b'\0').unwrap();
+        assert_eq!(paths, ["a\nb", "c\r"].map(PathBuf::from));
+    }
+}
@@ -1,10 +1,19 @@
 extern crate find_doubles;
 
 use std::env::{args, current_dir};
-use std::path::PathBuf;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{stdin, BufReader};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::str::FromStr;
 
-use find_doubles::{find_doubles, Backend, Comparison};
+use find_doubles::{
+    apply_action, find_doubles, find_listed_doubles, read_file_list, undo_action_log, Action,
+    ActionOptions, ActionReport, Backend, Comparison, DuplicateFile, DuplicateGroup,
+    DuplicateReport, HardLinkGroup, HashAlgorithm, HashOptions, KeepRule, OutputSink, Pattern,
+    ScanOptions, SymlinkPolicy,
+};
 
 const DEFAULT_COMP: &str = find_doubles::COMP_NAME;
 const DEFAULT_BACK_FILENAME: &str = find_doubles::BACK_SYNC;
@@ -13,11 +22,176 @@ const DEFAULT_BACK_HASH: &str = find_doubles::BACK_MULTI_THREADED;
 const ERROR_CODE_BAD_COMP: i32 = 1;
 const ERROR_CODE_BAD_DIR: i32 = 2;
 const ERROR_CODE_BAD_BACK: i32 = 3;
+const ERROR_CODE_BAD_OPTION: i32 = 4;
+const ERROR_CODE_SCAN_ERRORS: i32 = 5;
+
+const OPT_HASH: &str = "--hash";
+const OPT_VERIFY: &str = "--verify";
+const OPT_WORKERS: &str = "--workers";
+const OPT_MAX_OPEN_FILES: &str = "--max-open-files";
+const OPT_SYMLINKS: &str = "--symlinks";
+const OPT_ONE_FILE_SYSTEM: &str = "--one-file-system";
+const OPT_IGNORE_FILES: &str = "--ignore-files";
+const OPT_MIN_DEPTH: &str = "--min-depth";
+const OPT_MAX_DEPTH: &str = "--max-depth";
+const OPT_NO_HIDDEN: &str = "--no-hidden";
+const OPT_MIN_SIZE: &str = "--min-size";
+const OPT_MAX_SIZE: &str = "--max-size";
+const OPT_INCLUDE: &str = "--include";
+const OPT_EXCLUDE: &str = "--exclude";
+const OPT_INCLUDE_REGEX: &str = "--include-regex";
+const OPT_EXCLUDE_REGEX: &str = "--exclude-regex";
+const OPT_ROOT: &str = "--root";
+const OPT_REFERENCE: &str = "--reference";
+const OPT_FILES_FROM: &str = "--files-from";
+const OPT_NULL: &str = "--null";
+const OPT_ACTION: &str = "--action";
+const OPT_KEEP: &str = "--keep";
+const OPT_KEEP_PATTERN: &str = "--keep-pattern";
+const OPT_APPLY: &str = "--apply";
+const OPT_ABSOLUTE_LINKS: &str = "--absolute-links";
+const OPT_QUARANTINE: &str = "--quarantine";
+const OPT_LOG: &str = "--log";
+const OPT_UNDO: &str = "--undo";
+
+/// Prints the groups of duplicates on the standard output, tagging files with the number of their
+/// root when several roots are scanned, and those of reference directories.
+struct PrintGroups {
+    several_roots: bool,
+}
+
+impl PrintGroups {
+    fn print_file(&self, file: &DuplicateFile) {
+        let reference = if file.reference { " (reference)" } else { "" };
+        let symlink = if file.symlink { " (symlink)" } else { "" };
+        if self.several_roots {
+            println!(
+                "    - [{}] {}{}{}",
+                file.root + 1,
+                file.path.to_string_lossy(),
+                reference,
+                symlink
+            );
+        } else {
+            println!(
+                "    - {}{}{}",
+                file.path.to_string_lossy(),
+                reference,
+                symlink
+            );
+        }
+    }
+}
+
+impl OutputSink for PrintGroups {
+    fn group(&self, group: &DuplicateGroup) {
+        println!("{} :", group.key);
+        group.files.iter().for_each(|file| self.print_file(file));
+    }
+
+    fn hard_links(&self, group: &HardLinkGroup) {
+        println!("Already linked ({} bytes) :", group.size);
+        group.files.iter().for_each(|file| self.print_file(file));
+    }
+}
 
 fn main() {
+    let mut hash_options = HashOptions::default();
+    let mut verify = false;
+    let mut workers = None;
+    let mut max_open_files = None;
+    let mut symlinks = SymlinkPolicy::default();
+    let mut one_file_system = false;
+    let mut ignore_files = false;
+    let mut min_depth = None;
+    let mut max_depth = None;
+    let mut include_hidden = true;
+    let mut min_size = None;
+    let mut max_size = None;
+    let mut includes = Vec::new();
+    let mut excludes = Vec::new();
+    let mut extra_roots = Vec::new();
+    let mut references = Vec::new();
+    let mut files_from = None;
+    let mut separator = b'\n';
+    let mut action = None;
+    let mut keep = KeepRule::default();
+    let mut keep_patterns = Vec::new();
+    let mut apply = false;
+    let mut absolute_links = false;
+    let mut quarantine = None;
+    let mut log = None;
+    let mut undo = None;
+
+    let mut positional_args = Vec::new();
     let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            OPT_HASH => {
+                hash_options.algorithm = parse_option_value::<HashAlgorithm>(OPT_HASH, args.next())
+            }
+            OPT_VERIFY => verify = true,
+            OPT_ONE_FILE_SYSTEM => one_file_system = true,
+            OPT_IGNORE_FILES => ignore_files = true,
+            OPT_MIN_DEPTH => min_depth = Some(parse_option_value(OPT_MIN_DEPTH, args.next())),
+            OPT_MAX_DEPTH => max_depth = Some(parse_option_value(OPT_MAX_DEPTH, args.next())),
+            OPT_NO_HIDDEN => include_hidden = false,
+            OPT_MIN_SIZE => min_size = Some(parse_option_value(OPT_MIN_SIZE, args.next())),
+            OPT_MAX_SIZE => max_size = Some(parse_option_value(OPT_MAX_SIZE, args.next())),
+            OPT_INCLUDE => includes.push(parse_pattern(OPT_INCLUDE, args.next(), Pattern::glob)),
+            OPT_EXCLUDE => excludes.push(parse_pattern(OPT_EXCLUDE, args.next(), Pattern::glob)),
+            OPT_INCLUDE_REGEX => includes.push(parse_pattern(
+                OPT_INCLUDE_REGEX,
+                args.next(),
+                Pattern::regex,
+            )),
+            OPT_EXCLUDE_REGEX => excludes.push(parse_pattern(
+                OPT_EXCLUDE_REGEX,
+                args.next(),
+                Pattern::regex,
+            )),
+            OPT_REFERENCE => {
+                references.push(parse_option_value::<PathBuf>(OPT_REFERENCE, args.next()))
+            }
+            OPT_FILES_FROM => {
+                files_from = Some(parse_option_value::<PathBuf>(OPT_FILES_FROM, args.next()))
+            }
+            OPT_NULL => separator = b'\0',
+            OPT_ACTION => action = Some(parse_option_value::<Action>(OPT_ACTION, args.next())),
+            OPT_KEEP => keep = parse_option_value(OPT_KEEP, args.next()),
+            OPT_KEEP_PATTERN => {
+                keep_patterns.push(parse_pattern(OPT_KEEP_PATTERN, args.next(), Pattern::glob))
+            }
+            OPT_APPLY => apply = true,
+            OPT_ABSOLUTE_LINKS => absolute_links = true,
+            OPT_QUARANTINE => {
+                quarantine = Some(parse_option_value::<PathBuf>(OPT_QUARANTINE, args.next()))
+            }
+            OPT_LOG => log = Some(parse_option_value::<PathBuf>(OPT_LOG, args.next())),
+            OPT_UNDO => undo = Some(parse_option_value::<PathBuf>(OPT_UNDO, args.next())),
+            OPT_ROOT => extra_roots.push(parse_option_value::<PathBuf>(OPT_ROOT, args.next())),
+            OPT_WORKERS => workers = Some(parse_option_value(OPT_WORKERS, args.next())),
+            OPT_SYMLINKS => symlinks = parse_option_value(OPT_SYMLINKS, args.next()),
+            OPT_MAX_OPEN_FILES => {
+                max_open_files = Some(parse_option_value(OPT_MAX_OPEN_FILES, args.next()))
+            }
+            _ => positional_args.push(arg),
+        }
+    }
+
+    if let Some(log) = undo {
+        undo_log(&log, !apply);
+        return;
+    }
+
+    let mut args = positional_args.into_iter();
     let comp_arg1 = args.next();
-    let dir_arg2 = args.next();
+    // With a list of files, there is no directory argument.
+    let dir_arg2 = if files_from.is_none() {
+        args.next()
+    } else {
+        None
+    };
     let backend_arg3 = args.next();
 
     let comp: Comparison = match comp_arg1.as_ref().map_or(DEFAULT_COMP, |e| &e[..]).parse() {
@@ -27,9 +201,32 @@ fn main() {
             exit(ERROR_CODE_BAD_COMP);
         }
     };
+    eprintln!(
+        "Using file {} to compare files and detect duplicates.",
+        match comp {
+            Comparison::FileName => "name",
+            Comparison::Hash => "hash",
+            Comparison::Both => "name and hash",
+        }
+    );
+    if !matches!(comp, Comparison::FileName) {
+        eprintln!("Using {} hash algorithm.", hash_options.algorithm);
+    }
+
+    let listed = files_from.map(|list| read_listed_files(&list, separator));
+    if listed.is_some() && !extra_roots.is_empty() {
+        eprintln!(
+            "Error: option `{}` can't be used with `{}`.",
+            OPT_ROOT, OPT_FILES_FROM
+        );
+        exit(ERROR_CODE_BAD_OPTION);
+    }
 
     let dir = if let Some(dir) = dir_arg2 {
         PathBuf::from(&dir)
+    } else if listed.is_some() {
+        // Root of the listed files, whose paths are used as they were listed.
+        PathBuf::new()
     } else {
         let dir = current_dir().unwrap();
         eprintln!(
@@ -38,33 +235,84 @@ fn main() {
         );
         dir
     };
-    if !dir.is_dir() {
-        eprintln!(
-            "Error: provided argument `{}` is not a directory.",
-            dir.to_string_lossy()
-        );
-        exit(ERROR_CODE_BAD_DIR);
-    }
-
-    let backend: Backend = match backend_arg3
-        .as_ref()
-        .map_or(
-            if let Comparison::FileName = comp {
-                DEFAULT_BACK_FILENAME
-            } else {
-                DEFAULT_BACK_HASH
-            },
-            |e| &e[..],
-        )
-        .parse()
-    {
-        Ok(backend) => backend,
-        Err(err) => {
-            eprintln!("{}", err);
-            exit(ERROR_CODE_BAD_BACK);
+    let roots: Vec<_> = [dir].into_iter().chain(extra_roots).collect();
+    for root in roots.iter().filter(|_| listed.is_none()).chain(&references) {
+        if !root.is_dir() {
+            eprintln!(
+                "Error: provided argument `{}` is not a directory.",
+                root.to_string_lossy()
+            );
+            exit(ERROR_CODE_BAD_DIR);
+        }
+    }
+    let several_roots = roots.len() + references.len() > 1;
+    if several_roots {
+        if listed.is_some() {
+            eprintln!("Root [1] : listed files");
+        } else {
+            for (i, root) in roots.iter().enumerate() {
+                eprintln!("Root [{}] : {}", i + 1, root.to_string_lossy());
+            }
+        }
+        for (i, root) in references.iter().enumerate() {
+            eprintln!(
+                "Reference root [{}] : {}",
+                roots.len() + i + 1,
+                root.to_string_lossy()
+            );
+        }
+    }
+
+    let backend_arg = backend_arg3.as_ref().map_or(
+        if let Comparison::FileName = comp {
+            DEFAULT_BACK_FILENAME
+        } else {
+            DEFAULT_BACK_HASH
+        },
+        |e| &e[..],
+    );
+    let backends = if backend_arg.to_lowercase() == find_doubles::BACK_ALL {
+        eprintln!("Using all backends.");
+        Backend::ALL.to_vec()
+    } else {
+        match backend_arg.parse() {
+            Ok(backend) => {
+                eprintln!("Using backend {}.", backend_arg);
+                vec![backend]
+            }
+            Err(err) => {
+                eprintln!("{} Or `{}`.", err, find_doubles::BACK_ALL);
+                exit(ERROR_CODE_BAD_BACK);
+            }
         }
     };
 
+    let action_options = action.map(|action| {
+        if backends.len() > 1 {
+            eprintln!(
+                "Error: option `{}` can't be used with `{}` backends.",
+                OPT_ACTION,
+                find_doubles::BACK_ALL
+            );
+            exit(ERROR_CODE_BAD_OPTION);
+        }
+
+        let mut action_options = ActionOptions::new(action)
+            .keep(keep)
+            .dry_run(!apply)
+            .absolute_links(absolute_links);
+        if let Some(quarantine) = &quarantine {
+            action_options = action_options.quarantine(quarantine);
+        }
+        if let Some(log) = &log {
+            action_options = action_options.log(log);
+        }
+        for pattern in keep_patterns {
+            action_options = action_options.keep_pattern(pattern);
+        }
+        action_options
+    });
+
     let enable_output = if backend_arg3.is_some() {
         eprintln!("A backend was provided, we disable output.");
         false
@@ -72,5 +320,296 @@ fn main() {
         true
     };
 
-    find_doubles(enable_output, comp, backend, dir);
+    let mut options = ScanOptions::new()
+        .comparison(comp)
+        .hash_options(hash_options)
+        .verify(verify)
+        .symlinks(symlinks)
+        .one_file_system(one_file_system)
+        .ignore_files(ignore_files)
+        .include_hidden(include_hidden);
+    for reference in references {
+        options = options.reference(reference);
+    }
+    for pattern in includes {
+        options = options.include(pattern);
+    }
+    for pattern in excludes {
+        options = options.exclude(pattern);
+    }
+    if let Some(min_depth) = min_depth {
+        options = options.min_depth(min_depth);
+    }
+    if let Some(max_depth) = max_depth {
+        options = options.max_depth(max_depth);
+    }
+    if let Some(min_size) = min_size {
+        options = options.min_size(min_size);
+    }
+    if let Some(max_size) = max_size {
+        options = options.max_size(max_size);
+    }
+    if let Some(workers) = workers {
+        options = options.workers(workers);
+    }
+    if let Some(max_open_files) = max_open_files {
+        options = options.max_open_files(max_open_files);
+    }
+    if enable_output {
+        options = options.output(PrintGroups { several_roots });
+    }
+
+    if backends.len() > 1 {
+        eprintln!("Useless first try that should be much longer if the system hasn't already cached files.");
+        let report = scan(
+            &roots,
+            listed.as_deref(),
+            &options.clone().backend(Backend::MultiThreaded),
+        );
+        display_report(&report);
+        eprintln!("--------------------------------------------------------------------------------------------------------------------------------\n");
+    }
+
+    let mut scan_errors = false;
+    for backend in backends {
+        let report = scan(&roots, listed.as_deref(), &options.clone().backend(backend));
+        display_report(&report);
+        scan_errors |= !report.errors.is_empty();
+
+        if let Some(action_options) = &action_options {
+            let action_report = apply_action(&report, action_options);
+            display_action_report(&action_report);
+            scan_errors |= !action_report.errors.is_empty();
+        }
+    }
+
+    if scan_errors {
+        exit(ERROR_CODE_SCAN_ERRORS);
+    }
+}
+
+fn scan(roots: &[PathBuf], listed: Option<&[PathBuf]>, options: &ScanOptions) -> DuplicateReport {
+    match listed {
+        Some(files) => find_listed_doubles(files.to_vec(), options),
+        None => find_doubles(roots, options),
+    }
+}
+
+/// Reads the paths listed in the file `list`, or on the standard input for `-`.
+fn read_listed_files(list: &Path, separator: u8) -> Vec<PathBuf> {
+    let files = if list.as_os_str() == "-" {
+        read_file_list(stdin().lock(), separator)
+    } else {
+        File::open(list).and_then(|file| read_file_list(BufReader::new(file), separator))
+    };
+
+    match files {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!(
+                "Error when reading file list `{}` : {}",
+                list.to_string_lossy(),
+                err
+            );
+            exit(ERROR_CODE_BAD_OPTION);
+        }
+    }
+}
+
+fn display_report(report: &DuplicateReport) {
+    eprintln!("Backend {:?}", report.backend);
+    if let Some(algorithm) = report.hash_algorithm {
+        eprintln!("Hash algorithm {}", algorithm);
+    }
+
+    for err in report.errors.iter() {
+        eprintln!("{}", err);
+    }
+
+    for mismatch in report.mismatches.iter() {
+        eprintln!(
+            "Content mismatch between files sharing identifier `{}`, splitting them :",
+            mismatch.key
+        );
+        for (i, group) in mismatch.groups.iter().enumerate() {
+            eprintln!("    {} :", i + 1);
+            group
+                .iter()
+                .for_each(|file| eprintln!("        - {}", file.path.to_string_lossy()));
+        }
+    }
+
+    let stats = &report.stats;
+    eprintln!(
+        "    Stats : files {}, dirs {}, skipped {}, errors {}, read {} bytes, hashed {} bytes",
+        stats.files(),
+        stats.dirs(),
+        stats.skipped(),
+        stats.errors(),
+        stats.bytes_read(),
+        stats.bytes_hashed()
+    );
+    for mount_point in stats.mount_points() {
+        eprintln!(
+            "    Skipped mount point : {}",
+            mount_point.to_string_lossy()
+        );
+    }
+    if report.hash_algorithm.is_some() {
+        eprintln!(
+            "    Reclaimable : {} bytes in {} groups, {} groups already linked",
+            report.reclaimable_bytes(),
+            report.groups.len(),
+            report.hard_links.len()
+        );
+    }
+    for (phase, duration) in stats.timings() {
+        eprintln!("        {} : {}ms", phase, duration.as_millis());
+    }
+
+    eprintln!("    Finished in {}s\n", stats.duration().as_secs());
+}
+
+fn display_action_report(report: &ActionReport) {
+    // Moving files to a quarantine on the same filesystem doesn't free any space.
+    let (reclaiming, reclaimed) = match report.action {
+        Action::MoveTo => ("moving", "moved"),
+        _ => ("reclaiming", "reclaimed"),
+    };
+    let (verb, done, relation) = match report.action {
+        Action::Delete => ("delete", "Deleted", ", keeping"),
+        Action::HardLink => ("replace", "Replaced", " with a hard link to"),
+        Action::Reflink => ("reflink", "Reflinked", " to"),
+        Action::Symlink => ("replace", "Replaced", " with a symlink to"),
+        Action::MoveTo => ("move", "Moved", " to quarantine, keeping"),
+    };
+
+    for step in report.steps.iter() {
+        if report.dry_run {
+            println!(
+                "Would {} `{}`{} `{}`",
+                verb,
+                step.path.to_string_lossy(),
+                relation,
+                step.kept.to_string_lossy()
+            );
+        } else {
+            println!(
+                "{} `{}`{} `{}`",
+                done,
+                step.path.to_string_lossy(),
+                relation,
+                step.kept.to_string_lossy()
+            );
+        }
+    }
+
+    for err in report.errors.iter() {
+        eprintln!("{}", err);
+    }
+
+    if report.dry_run {
+        eprintln!(
+            "Dry run : would {} {} files, {} {} bytes. Use `{}` to do it.",
+            verb,
+            report.steps.len(),
+            reclaiming,
+            report.reclaimed_bytes(),
+            OPT_APPLY
+        );
+    } else {
+        eprintln!(
+            "{} {} files, {} {} bytes.",
+            done,
+            report.steps.len(),
+            reclaimed,
+            report.reclaimed_bytes()
+        );
+    }
+    if let Some(log) = &report.log {
+        eprintln!(
+            "Logged in `{}`, use `{} {}` to undo it.",
+            log.to_string_lossy(),
+            OPT_UNDO,
+            log.to_string_lossy()
+        );
+    }
+}
+
+/// Undoes the action log `log`, only telling what would be undone for a dry run.
+fn undo_log(log: &Path, dry_run: bool) {
+    let report = match undo_action_log(log, dry_run) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(ERROR_CODE_BAD_OPTION);
+        }
+    };
+
+    for entry in report.restored.iter() {
+        println!(
+            "{} `{}` from `{}`",
+            if dry_run { "Would restore" } else { "Restored" },
+            entry.path.to_string_lossy(),
+            entry
+                .destination
+                .as_ref()
+                .unwrap_or(&entry.kept)
+                .to_string_lossy()
+        );
+    }
+    for err in report.errors.iter() {
+        eprintln!("{}", err);
+    }
+
+    if dry_run {
+        eprintln!(
+            "Dry run : would restore {} files. Use `{}` to do it.",
+            report.restored.len(),
+            OPT_APPLY
+        );
+    } else {
+        eprintln!("Restored {} files.", report.restored.len());
+    }
+    if !report.errors.is_empty() {
+        exit(ERROR_CODE_SCAN_ERRORS);
+    }
+}
+
+fn parse_option_value<T>(option: &str, value: Option<String>) -> T
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let Some(value) = value else {
+        eprintln!("Error: missing value for option `{}`.", option);
+        exit(ERROR_CODE_BAD_OPTION);
+    };
+
+    match value.parse() {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!(
+                "Error: invalid value `{}` for option `{}` : {}",
+                value, option, err
+            );
+            exit(ERROR_CODE_BAD_OPTION);
+        }
+    }
+}
+
+fn parse_pattern(
+    option: &str,
+    value: Option<String>,
+    parse: fn(&str) -> Result<Pattern, String>,
+) -> Pattern {
+    let value: String = parse_option_value(option, value);
+
+    match parse(&value) {
+        Ok(pattern) => pattern,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(ERROR_CODE_BAD_OPTION);
+        }
+    }
 }
@@ -1,116 +1,126 @@
-use std::collections::HashMap;
 use std::fs::read_dir;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::Ordering;
 use std::sync::mpsc;
+use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
 extern crate loole;
-use loole::{unbounded, Sender};
+use loole::unbounded;
 
+use super::walk::{classify, list_files, Dir, Entry, FoundFile};
 use super::{
-    get_file_id_by_both, get_file_id_by_file_name, get_file_id_by_hash, Comparison, CD, CF,
+    find_doubles_by_stages, get_file_id, DuplicateGroup, FileKey, FindDoublesError, HardLinkGroup,
+    HashOptions, Operation, Phase, ScanOptions, ScanStats, Stage,
 };
 
-pub fn find_doubles(comp: Comparison, dir: PathBuf) -> HashMap<String, Vec<PathBuf>> {
+pub fn find_doubles(
+    options: &Arc<ScanOptions>,
+    roots: Vec<Dir>,
+    listed: Vec<PathBuf>,
+    stats: &Arc<ScanStats>,
+) -> (Vec<DuplicateGroup>, Vec<HardLinkGroup>) {
+    let hash_options = options.hash_options;
+
+    let start = Instant::now();
+    let mut files = list_files(listed, options, stats);
+    for dir in roots {
+        enter_dir(&mut files, dir, options, stats);
+    }
+    stats.add_timing(Phase::Walk, start.elapsed());
+
+    find_doubles_by_stages(options, files, stats, |stage, files| {
+        get_file_ids(stage, hash_options, files, options, stats)
+    })
+}
+
+fn get_file_ids(
+    stage: Stage,
+    hash_options: HashOptions,
+    files: Vec<PathBuf>,
+    options: &ScanOptions,
+    stats: &ScanStats,
+) -> Vec<(FileKey, PathBuf)> {
     thread::scope(move |s| {
         let (tx, rx) = unbounded::<PathBuf>();
-        let frx = {
-            let (ftx, frx) = mpsc::channel::<(String, PathBuf)>();
+        let (ftx, frx) = mpsc::channel::<(FileKey, PathBuf)>();
 
-            for _ in 0..=thread::available_parallelism().unwrap().into() {
-                let ftx = ftx.clone();
-                let rx = rx.clone();
-                s.spawn(move || {
-                    for p in rx {
-                        if let Some(id) = enter_file(&p, comp) {
-                            ftx.send((id, p)).unwrap();
-                        }
+        for _ in 0..options.worker_count() {
+            let ftx = ftx.clone();
+            let rx = rx.clone();
+            s.spawn(move || {
+                for p in rx {
+                    if let Some(id) = enter_file(&p, stage, hash_options, stats) {
+                        ftx.send((id, p)).unwrap();
                     }
-                });
-            }
-
-            frx
-        };
-
-        let handle = s.spawn(move || {
-            let mut files: HashMap<String, Vec<PathBuf>> = HashMap::new();
-            for (file_id, file_path) in frx {
-                let vec_opt = files.entry(file_id).or_default();
-                vec_opt.push(file_path);
-            }
-            files
-        });
+                }
+            });
+        }
+        drop(ftx);
 
-        enter_dir(tx, dir);
+        for p in files {
+            tx.send(p).unwrap();
+        }
+        drop(tx);
 
-        handle.join().unwrap()
+        frx.into_iter().collect()
     })
 }
 
-fn enter_file(file_path: &Path, comp: Comparison) -> Option<String> {
+fn enter_file(
+    file_path: &Path,
+    stage: Stage,
+    hash_options: HashOptions,
+    stats: &ScanStats,
+) -> Option<FileKey> {
     /*
     if !file_path.is_file() {
         panic!("Not a file : `{}`!", file_path.to_string_lossy());
     }
     */
 
-    CF.fetch_add(1, Ordering::Relaxed);
-
     // println!("file {}", file_path.to_string_lossy());
-    let file_id = match comp {
-        Comparison::FileName => get_file_id_by_file_name(file_path),
-        Comparison::Hash => get_file_id_by_hash(file_path),
-        Comparison::Both => get_file_id_by_both(file_path),
-    };
-
-    file_id
-        .inspect_err(|err| {
-            eprintln!(
-                "Error when getting file identifier for `{}` : {}",
-                file_path.to_string_lossy(),
-                err
-            )
-        })
+    get_file_id(stage, hash_options, file_path, stats)
+        .map_err(|err| stats.push_error(err))
         .ok()
 }
 
-fn enter_dir(known_names: Sender<PathBuf>, dir_path: PathBuf) {
+fn enter_dir(files: &mut Vec<FoundFile>, dir: Dir, options: &ScanOptions, stats: &ScanStats) {
     /*
     if !dir_path.is_dir() {
-        panic!("Not a directory : `{}`!", dir_path.to_string_lossy());
+        panic!("Not a directory : `{}`!", dir.path.to_string_lossy());
     }
     */
 
-    CD.fetch_add(1, Ordering::Relaxed);
+    stats.add_dir();
 
-    // println!("dir  {}", dir_path.to_string_lossy());
-    match read_dir(&dir_path) {
+    // println!("dir  {}", dir.path.to_string_lossy());
+    match read_dir(&dir.path) {
         Ok(entries) => entries.for_each(|entry_res| match entry_res {
             Ok(entry) => match entry.metadata() {
-                Ok(metadata) => {
-                    if metadata.is_dir() {
-                        enter_dir(known_names.clone(), entry.path());
-                    } else if metadata.is_file() {
-                        known_names.send(entry.path()).unwrap();
+                Ok(metadata) => match classify(&dir, entry.path(), metadata, options, stats) {
+                    Ok(Entry::Dir(sub_dir)) => {
+                        enter_dir(files, sub_dir, options, stats);
                     }
-                }
-                Err(err) => eprintln!(
-                    "Error when reading entry metadata `{}` : {}",
-                    entry.path().to_string_lossy(),
-                    err
-                ),
+                    Ok(Entry::File(file)) => {
+                        stats.add_file();
+                        files.push(file);
+                    }
+                    Ok(Entry::Skipped) => stats.add_skipped(),
+                    Err(err) => stats.push_error(err),
+                },
+                Err(err) => stats.push_error(FindDoublesError::io(
+                    &entry.path(),
+                    Operation::ReadMetadata,
+                    err,
+                )),
             },
-            Err(err) => eprintln!(
-                "Error when reading dir entry `{}` : {}",
-                dir_path.to_string_lossy(),
-                err
-            ),
+            Err(err) => stats.push_error(FindDoublesError::io(
+                &dir.path,
+                Operation::ReadDirEntry,
+                err,
+            )),
         }),
-        Err(err) => eprintln!(
-            "Error when reading dir `{}` : {}",
-            dir_path.to_string_lossy(),
-            err
-        ),
+        Err(err) => stats.push_error(FindDoublesError::io(&dir.path, Operation::ReadDir, err)),
     }
 }
@@ -0,0 +1,314 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+
+use super::filter::{Filters, Pattern};
+use super::{
+    Backend, Comparison, DuplicateGroup, FindDoublesError, HardLinkGroup, HashAlgorithm,
+    HashOptions, SYMLINK_FOLLOW, SYMLINK_IGNORE, SYMLINK_REPORT,
+};
+
+/// Default number of files or directories the async backends keep open at the same time.
+pub const DEFAULT_MAX_OPEN_FILES: usize = 10;
+
+/// Default minimum size of the scanned files when comparing their content, so empty files are left
+/// out.
+pub const DEFAULT_MIN_SIZE: u64 = 1;
+
+/// Predicate deciding whether a path found while walking is scanned, see [`ScanOptions::filter`].
+pub type PathFilter = Arc<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// What the walk does with symlinks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Leaves symlinks out of the scan.
+    #[default]
+    Ignore,
+    /// Walks through symlinks as if they were the file or directory they point to. A directory
+    /// already containing the one a symlink points to isn't entered again, to avoid loops. A
+    /// symlink to a file is a path of its own, not a hard link of its target.
+    Follow,
+    /// Lists symlinks to files as files of their own, with the content of their target, without
+    /// entering symlinks to directories.
+    Report,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = match s.to_lowercase().as_ref() {
+            SYMLINK_IGNORE => SymlinkPolicy::Ignore,
+            SYMLINK_FOLLOW => SymlinkPolicy::Follow,
+            SYMLINK_REPORT => SymlinkPolicy::Report,
+            _ => {
+                return Err(format!(
+                    "Could not parse `{}` as symlink policy, please use `{}`, `{}`, or `{}`.",
+                    s, SYMLINK_IGNORE, SYMLINK_FOLLOW, SYMLINK_REPORT
+                ));
+            }
+        };
+
+        Ok(policy)
+    }
+}
+
+/// Receives the results of a scan, see [`ScanOptions::output`].
+pub trait OutputSink: Send + Sync {
+    /// Called for each group of duplicates, once the scan (and verification) is finished.
+    fn group(&self, group: &DuplicateGroup);
+
+    /// Called for each group of paths that are hard links to the same file.
+    fn hard_links(&self, _group: &HardLinkGroup) {}
+
+    /// Called for each non-fatal error met during the scan.
+    fn error(&self, _error: &FindDoublesError) {}
+}
+
+/// Configuration of a scan, built by chaining its methods :
+///
+/// ```no_run
+/// use find_doubles::{find_doubles, Backend, Comparison, ScanOptions};
+///
+/// let options = ScanOptions::new()
+///     .comparison(Comparison::Hash)
+///     .backend(Backend::MultiThreaded)
+///     .workers(4)
+///     .verify(true);
+/// let report = find_doubles(&["some/dir".into(), "other/dir".into()], &options);
+/// ```
+#[derive(Clone)]
+pub struct ScanOptions {
+    pub(crate) comparison: Comparison,
+    pub(crate) backend: Backend,
+    pub(crate) hash_options: HashOptions,
+    pub(crate) verify: bool,
+    pub(crate) workers: Option<usize>,
+    pub(crate) max_open_files: usize,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) one_file_system: bool,
+    pub(crate) ignore_files: bool,
+    pub(crate) min_depth: usize,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) include_hidden: bool,
+    pub(crate) min_size: Option<u64>,
+    pub(crate) max_size: Option<u64>,
+    pub(crate) filters: Filters,
+    pub(crate) filter: Option<PathFilter>,
+    pub(crate) references: Vec<PathBuf>,
+    pub(crate) output: Option<Arc<dyn OutputSink>>,
+}
+
+impl ScanOptions {
+    /// Compares file names with the sync backend, like the CLI without arguments.
+    pub fn new() -> Self {
+        ScanOptions {
+            comparison: Comparison::FileName,
+            backend: Backend::Sync,
+            hash_options: HashOptions::default(),
+            verify: false,
+            workers: None,
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            symlinks: SymlinkPolicy::default(),
+            one_file_system: false,
+            ignore_files: false,
+            min_depth: 0,
+            max_depth: None,
+            include_hidden: true,
+            min_size: None,
+            max_size: None,
+            filters: Filters::default(),
+            filter: None,
+            references: Vec::new(),
+            output: None,
+        }
+    }
+
+    pub fn comparison(mut self, comparison: Comparison) -> Self {
+        self.comparison = comparison;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn hash_options(mut self, hash_options: HashOptions) -> Self {
+        self.hash_options = hash_options;
+        self
+    }
+
+    pub fn hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash_options.algorithm = algorithm;
+        self
+    }
+
+    /// Size in bytes of the buffer used to read files, to hash or to verify them.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.hash_options.buffer_size = buffer_size;
+        self
+    }
+
+    /// Compares byte by byte the files grouped by their hash.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Number of threads of the multithreaded and multi async backends, one more than the number
+    /// of available cores by default. Other backends use a fixed number of threads.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = Some(workers.max(1));
+        self
+    }
+
+    /// Number of files or directories the async backends keep open at the same time.
+    pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files.max(1);
+        self
+    }
+
+    /// What to do with symlinks, they are ignored by default.
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Doesn't enter the directories on another device than the root of the scan, such as network
+    /// or FUSE mounts. They are listed in [`ScanStats::mount_points`](crate::ScanStats::mount_points).
+    pub fn one_file_system(mut self, one_file_system: bool) -> Self {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// Skips the files and directories listed in the `.gitignore`, `.ignore` and
+    /// `.find_doubles_ignore` files of each directory, with the same syntax as `.gitignore`. Rules
+    /// of a directory take precedence over those of its parents, and in a directory
+    /// `.find_doubles_ignore` takes precedence over `.ignore`, itself over `.gitignore`.
+    pub fn ignore_files(mut self, ignore_files: bool) -> Self {
+        self.ignore_files = ignore_files;
+        self
+    }
+
+    /// Leaves out the files less than `min_depth` directories deep, the files directly in the
+    /// scanned directory being at depth `1`.
+    pub fn min_depth(mut self, min_depth: usize) -> Self {
+        self.min_depth = min_depth;
+        self
+    }
+
+    /// Doesn't look for files more than `max_depth` directories deep, the files directly in the
+    /// scanned directory being at depth `1`.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Whether files and directories whose name starts with a `.` are scanned, which is the case
+    /// by default.
+    pub fn include_hidden(mut self, include_hidden: bool) -> Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    /// Leaves out the files smaller than `min_size` bytes. When comparing content, it is `1` by
+    /// default so empty files, which are all identical, don't flood the results : use `0` to
+    /// include them. Comparing names keeps all files by default.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = Some(min_size);
+        self
+    }
+
+    /// Leaves out the files bigger than `max_size` bytes.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Only scans the files matching `pattern`, or one of the other include patterns. Directories
+    /// are still entered.
+    pub fn include(mut self, pattern: Pattern) -> Self {
+        self.filters.include.push(pattern);
+        self
+    }
+
+    /// Leaves out the files and directories matching `pattern`, excluded directories aren't
+    /// entered.
+    pub fn exclude(mut self, pattern: Pattern) -> Self {
+        self.filters.exclude.push(pattern);
+        self
+    }
+
+    /// Only scans the paths for which `filter` returns `true`. A rejected directory isn't entered.
+    pub fn filter(mut self, filter: impl Fn(&Path) -> bool + Send + Sync + 'static) -> Self {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Scans `root` as a reference directory, in addition to the roots given to
+    /// [`find_doubles`](crate::find_doubles) which become candidates. Only the groups with both
+    /// reference and candidate files are then reported : the candidates whose content is already
+    /// in a reference directory, along with their copies there.
+    pub fn reference(mut self, root: impl Into<PathBuf>) -> Self {
+        self.references.push(root.into());
+        self
+    }
+
+    /// Sends the groups of duplicates and the errors to `output` at the end of the scan, in
+    /// addition to returning them.
+    pub fn output(mut self, output: impl OutputSink + 'static) -> Self {
+        self.output = Some(Arc::new(output));
+        self
+    }
+
+    pub(crate) fn worker_count(&self) -> usize {
+        self.workers
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |cores| cores.get()) + 1)
+    }
+
+    pub(crate) fn accepts_size(&self, size: u64) -> bool {
+        let min_size = self.min_size.unwrap_or(match self.comparison {
+            Comparison::FileName => 0,
+            Comparison::Hash | Comparison::Both => DEFAULT_MIN_SIZE,
+        });
+        size >= min_size && self.max_size.is_none_or(|max_size| size <= max_size)
+    }
+
+    pub(crate) fn accepts(&self, path: &Path) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter(path))
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions::new()
+    }
+}
+
+impl fmt::Debug for ScanOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScanOptions")
+            .field("comparison", &self.comparison)
+            .field("backend", &self.backend)
+            .field("hash_options", &self.hash_options)
+            .field("verify", &self.verify)
+            .field("workers", &self.workers)
+            .field("max_open_files", &self.max_open_files)
+            .field("symlinks", &self.symlinks)
+            .field("one_file_system", &self.one_file_system)
+            .field("ignore_files", &self.ignore_files)
+            .field("min_depth", &self.min_depth)
+            .field("max_depth", &self.max_depth)
+            .field("include_hidden", &self.include_hidden)
+            .field("min_size", &self.min_size)
+            .field("max_size", &self.max_size)
+            .field("filters", &self.filters)
+            .field("filter", &self.filter.is_some())
+            .field("references", &self.references)
+            .field("output", &self.output.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,163 @@
+#[cfg(target_os = "linux")]
+extern crate libc;
+
+use std::path::Path;
+
+use super::FindDoublesError;
+
+/// Makes the file at `path` share the data blocks of `kept`, on filesystems supporting it such as
+/// Btrfs or XFS, while both files stay independent : writing to one of them later on doesn't change
+/// the other one.
+///
+/// The blocks are deduplicated in place with the `FIDEDUPERANGE` ioctl, for which the kernel checks
+/// the contents are equal. Only if the filesystem doesn't support it, `path` is replaced by a clone
+/// of `kept` made with the `FICLONE` ioctl under a temporary name, with the permissions, owner and
+/// timestamps of `path`, but not its extended attributes.
+#[cfg(target_os = "linux")]
+pub fn reflink(kept: &Path, path: &Path) -> Result<(), FindDoublesError> {
+    use std::fs::{File, FileTimes, OpenOptions};
+    use std::io;
+    use std::os::unix::fs::{fchown, MetadataExt};
+    use std::os::unix::io::AsRawFd;
+
+    use super::action::replace_with;
+    use super::Operation;
+
+    let source =
+        File::open(kept).map_err(|err| FindDoublesError::io(kept, Operation::Open, err))?;
+    let target = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|err| FindDoublesError::io(path, Operation::Open, err))?;
+    let len = source
+        .metadata()
+        .map_err(|err| FindDoublesError::io(kept, Operation::ReadMetadata, err))?
+        .len();
+
+    match dedupe_range(&source, &target, len) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(FindDoublesError::Changed {
+            path: path.to_owned(),
+        }),
+        Err(err) if matches!(err.raw_os_error(), Some(libc::EOPNOTSUPP | libc::ENOTTY)) => {
+            let metadata = target
+                .metadata()
+                .map_err(|err| FindDoublesError::io(path, Operation::ReadMetadata, err))?;
+            let times = FileTimes::new()
+                .set_accessed(
+                    metadata
+                        .accessed()
+                        .map_err(|err| FindDoublesError::io(path, Operation::ReadMetadata, err))?,
+                )
+                .set_modified(
+                    metadata
+                        .modified()
+                        .map_err(|err| FindDoublesError::io(path, Operation::ReadMetadata, err))?,
+                );
+            drop(target);
+
+            replace_with(
+                path,
+                |tmp_path| {
+                    let clone = OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(tmp_path)?;
+                    // SAFETY: both file descriptors are open for the duration of the call.
+                    let res = unsafe {
+                        libc::ioctl(clone.as_raw_fd(), libc::FICLONE, source.as_raw_fd())
+                    };
+                    if res < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    // The owner is changed first, as it may clear the setuid and setgid bits.
+                    fchown(&clone, Some(metadata.uid()), Some(metadata.gid()))?;
+                    clone.set_permissions(metadata.permissions())?;
+                    clone.set_times(times)
+                },
+                Operation::Clone,
+            )
+        }
+        Err(err) => Err(FindDoublesError::io(path, Operation::Clone, err)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reflink(_kept: &Path, path: &Path) -> Result<(), FindDoublesError> {
+    use std::io::{self, ErrorKind};
+
+    use super::Operation;
+
+    Err(FindDoublesError::io(
+        path,
+        Operation::Clone,
+        io::Error::from(ErrorKind::Unsupported),
+    ))
+}
+
+/// `_IOWR(0x94, 54, struct file_dedupe_range)`, missing from `libc`.
+#[cfg(target_os = "linux")]
+const FIDEDUPERANGE: libc::Ioctl = 0xC018_9436 as libc::Ioctl;
+#[cfg(target_os = "linux")]
+const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+/// Filesystems may deduplicate less than asked in one call, Btrfs for instance limits it to 16 MiB.
+#[cfg(target_os = "linux")]
+const DEDUPE_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// `struct file_dedupe_range` of `linux/fs.h` with a single `struct file_dedupe_range_info`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FileDedupeRange {
+    src_offset: u64,
+    src_length: u64,
+    dest_count: u16,
+    reserved1: u16,
+    reserved2: u32,
+    dest_fd: i64,
+    dest_offset: u64,
+    bytes_deduped: u64,
+    status: i32,
+    reserved: u32,
+}
+
+/// Deduplicates the first `len` bytes of `target` with those of `source`, returning `false` if the
+/// kernel found their contents differ.
+#[cfg(target_os = "linux")]
+fn dedupe_range(source: &std::fs::File, target: &std::fs::File, len: u64) -> std::io::Result<bool> {
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    let mut offset = 0;
+    while offset < len {
+        let mut range = FileDedupeRange {
+            src_offset: offset,
+            src_length: DEDUPE_CHUNK_SIZE.min(len - offset),
+            dest_count: 1,
+            reserved1: 0,
+            reserved2: 0,
+            dest_fd: target.as_raw_fd() as i64,
+            dest_offset: offset,
+            bytes_deduped: 0,
+            status: 0,
+            reserved: 0,
+        };
+
+        // SAFETY: `range` is laid out as the kernel expects for one destination and outlives the
+        // call, both file descriptors are open.
+        if unsafe { libc::ioctl(source.as_raw_fd(), FIDEDUPERANGE, &mut range) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if range.status < 0 {
+            return Err(io::Error::from_raw_os_error(-range.status));
+        }
+        if range.status == FILE_DEDUPE_RANGE_DIFFERS {
+            return Ok(false);
+        }
+        if range.bytes_deduped == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        offset += range.bytes_deduped;
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use super::{Backend, Comparison, FileKey, FindDoublesError, HashAlgorithm, ScanStats};
+
+/// Result of a scan for duplicate files.
+#[derive(Debug)]
+pub struct DuplicateReport {
+    pub comparison: Comparison,
+    /// Algorithm used to hash files content, `None` when only comparing file names.
+    pub hash_algorithm: Option<HashAlgorithm>,
+    pub backend: Backend,
+    /// Directories that were scanned, [`DuplicateFile::root`] is an index in it. The reference
+    /// directories come after the candidates.
+    pub roots: Vec<PathBuf>,
+    /// Groups of at least two files considered duplicates of each other.
+    pub groups: Vec<DuplicateGroup>,
+    /// Files sharing the same hash whose content turned out to be different during verification.
+    pub mismatches: Vec<Mismatch>,
+    /// Paths already linked to the same file, only the first one of each is part of `groups`.
+    pub hard_links: Vec<HardLinkGroup>,
+    pub stats: ScanStats,
+    /// Non-fatal errors, the files or directories concerned are left out of the groups.
+    pub errors: Vec<FindDoublesError>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DuplicateGroup {
+    pub key: FileKey,
+    /// Size in bytes of each file of the group. When only comparing file names, files can have
+    /// different sizes and this is the size of the biggest one.
+    pub size: u64,
+    pub files: Vec<DuplicateFile>,
+}
+
+/// Path of a file, tagged with the root it was found in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateFile {
+    pub path: PathBuf,
+    /// Index of the root in [`DuplicateReport::roots`].
+    pub root: usize,
+    /// Whether the root is a reference directory, see
+    /// [`ScanOptions::reference`](crate::ScanOptions::reference).
+    pub reference: bool,
+    /// Whether the path is a symlink to a file with the content of the group, see
+    /// [`SymlinkPolicy`](crate::SymlinkPolicy). Removing it doesn't free any space.
+    pub symlink: bool,
+}
+
+impl DuplicateReport {
+    /// Space that would be freed by keeping only one file of each group.
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.groups
+            .iter()
+            .map(DuplicateGroup::reclaimable_bytes)
+            .sum()
+    }
+}
+
+impl DuplicateGroup {
+    /// Space that would be freed by keeping only one file of the group, or only the reference files
+    /// when there are some. Hard links to the same file only appear once in a group, and symlinks
+    /// don't take the space of their target, so they don't count. As a symlink is never the only
+    /// copy kept, nothing is freed when the reference files are all symlinks.
+    pub fn reclaimable_bytes(&self) -> u64 {
+        let candidates = self
+            .files
+            .iter()
+            .filter(|file| !file.reference && !file.symlink)
+            .count() as u64;
+        let has_references = self.files.iter().any(|file| file.reference);
+        let has_regular_references = self
+            .files
+            .iter()
+            .any(|file| file.reference && !file.symlink);
+
+        if has_regular_references {
+            self.size * candidates
+        } else if has_references {
+            0
+        } else {
+            self.size * candidates.saturating_sub(1)
+        }
+    }
+}
+
+/// Paths that are hard links to the same file, so they already take the space of only one file.
+#[derive(Clone, Debug)]
+pub struct HardLinkGroup {
+    pub size: u64,
+    pub files: Vec<DuplicateFile>,
+}
+
+/// Files that were grouped by their hash, but whose content isn't the same : either a hash
+/// collision or a file modified during the scan.
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    pub key: FileKey,
+    /// Files of the original group, split into groups of files with the same content.
+    pub groups: Vec<Vec<DuplicateFile>>,
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::FindDoublesError;
+
+/// Step of a scan whose duration is measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Walking through directories to list files.
+    Walk,
+    /// Hashing the first block of files.
+    HeadHash,
+    /// Hashing the last block of files.
+    TailHash,
+    /// Computing the final identifier of files (name and/or hash of the whole content).
+    Identify,
+    /// Byte for byte comparison of duplicates.
+    Verify,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Walk => "walk",
+            Phase::HeadHash => "head hash",
+            Phase::TailHash => "tail hash",
+            Phase::Identify => "identify",
+            Phase::Verify => "verify",
+        })
+    }
+}
+
+/// Statistics of one scan, shared by all the tasks of a backend, so several scans can run at the
+/// same time without mixing their numbers.
+#[derive(Debug, Default)]
+pub struct ScanStats {
+    files: AtomicU64,
+    dirs: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_hashed: AtomicU64,
+    errors: AtomicU64,
+    skipped: AtomicU64,
+    timings: Mutex<Vec<(Phase, Duration)>>,
+    mount_points: Mutex<Vec<PathBuf>>,
+    /// Details of the errors, moved to the report at the end of the scan.
+    error_list: Mutex<Vec<FindDoublesError>>,
+}
+
+impl ScanStats {
+    /// Number of regular files found.
+    pub fn files(&self) -> u64 {
+        self.files.load(Ordering::Acquire)
+    }
+
+    /// Number of directories entered.
+    pub fn dirs(&self) -> u64 {
+        self.dirs.load(Ordering::Acquire)
+    }
+
+    /// Number of bytes read from files, to hash or to compare them.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Acquire)
+    }
+
+    /// Number of bytes given to the hash algorithm.
+    pub fn bytes_hashed(&self) -> u64 {
+        self.bytes_hashed.load(Ordering::Acquire)
+    }
+
+    /// Number of non-fatal errors met.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Acquire)
+    }
+
+    /// Number of entries left out of the scan : the entries that are neither regular files nor
+    /// directories, but also those left out by the hidden, ignore, depth, size and path filters,
+    /// and the mount points.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Acquire)
+    }
+
+    /// Directories left out because they are on another device than the root of the scan, see
+    /// [`ScanOptions::one_file_system`](crate::ScanOptions::one_file_system).
+    pub fn mount_points(&self) -> Vec<PathBuf> {
+        self.mount_points.lock().unwrap().clone()
+    }
+
+    /// Time spent in each phase of the scan, in the order they ran.
+    pub fn timings(&self) -> Vec<(Phase, Duration)> {
+        self.timings.lock().unwrap().clone()
+    }
+
+    /// Total time of the scan.
+    pub fn duration(&self) -> Duration {
+        self.timings.lock().unwrap().iter().map(|(_, d)| *d).sum()
+    }
+
+    pub(crate) fn add_file(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_dir(&self) {
+        self.dirs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes_read(&self, len: usize) {
+        self.bytes_read.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_bytes_hashed(&self, len: usize) {
+        self.bytes_hashed.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a mount point, counted as skipped by the walker like any other entry left out.
+    pub(crate) fn add_mount_point(&self, path: PathBuf) {
+        self.mount_points.lock().unwrap().push(path);
+    }
+
+    pub(crate) fn add_timing(&self, phase: Phase, duration: Duration) {
+        self.timings.lock().unwrap().push((phase, duration));
+    }
+
+    pub(crate) fn push_error(&self, err: FindDoublesError) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.error_list.lock().unwrap().push(err);
+    }
+
+    pub(crate) fn take_errors(&self) -> Vec<FindDoublesError> {
+        std::mem::take(&mut self.error_list.lock().unwrap())
+    }
+}
+
+impl Clone for ScanStats {
+    fn clone(&self) -> Self {
+        ScanStats {
+            files: AtomicU64::new(self.files()),
+            dirs: AtomicU64::new(self.dirs()),
+            bytes_read: AtomicU64::new(self.bytes_read()),
+            bytes_hashed: AtomicU64::new(self.bytes_hashed()),
+            errors: AtomicU64::new(self.errors()),
+            skipped: AtomicU64::new(self.skipped()),
+            timings: Mutex::new(self.timings()),
+            mount_points: Mutex::new(self.mount_points()),
+            error_list: Mutex::new(self.error_list.lock().unwrap().clone()),
+        }
+    }
+}
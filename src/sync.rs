@@ -1,30 +1,44 @@
-use std::collections::HashMap;
-use std::fmt::Display;
 use std::fs::read_dir;
-use std::path::{Path, PathBuf};
-use std::sync::atomic::Ordering;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
+use super::walk::{classify, list_files, Dir, Entry, FoundFile};
 use super::{
-    get_file_id_by_both, get_file_id_by_file_name, get_file_id_by_hash, Comparison, CD, CF,
+    find_doubles_by_stages, get_file_id, DuplicateGroup, FileKey, FindDoublesError, HardLinkGroup,
+    HashOptions, Operation, Phase, ScanOptions, ScanStats, Stage,
 };
 
-type FnGetFileId<E> = (dyn Fn(&Path) -> Result<String, E>);
+pub fn find_doubles(
+    options: &Arc<ScanOptions>,
+    roots: Vec<Dir>,
+    listed: Vec<PathBuf>,
+    stats: &Arc<ScanStats>,
+) -> (Vec<DuplicateGroup>, Vec<HardLinkGroup>) {
+    let hash_options = options.hash_options;
 
-pub fn find_doubles(comp: Comparison, dir: PathBuf) -> HashMap<String, Vec<PathBuf>> {
-    let mut files = HashMap::new();
-    match comp {
-        Comparison::FileName => enter_dir(&mut files, dir, &get_file_id_by_file_name),
-        Comparison::Hash => enter_dir(&mut files, dir, &get_file_id_by_hash),
-        Comparison::Both => enter_dir(&mut files, dir, &get_file_id_by_both),
+    let start = Instant::now();
+    let mut files = list_files(listed, options, stats);
+    for dir in roots {
+        enter_dir(&mut files, dir, options, stats);
     }
+    stats.add_timing(Phase::Walk, start.elapsed());
 
-    files
+    find_doubles_by_stages(options, files, stats, |stage, files| {
+        let mut known_names = Vec::with_capacity(files.len());
+        for file_path in files {
+            enter_file(&mut known_names, file_path, stage, hash_options, stats);
+        }
+        known_names
+    })
 }
 
-fn enter_file<E: Display>(
-    known_names: &mut HashMap<String, Vec<PathBuf>>,
+fn enter_file(
+    known_names: &mut Vec<(FileKey, PathBuf)>,
     file_path: PathBuf,
-    get_file_id: &FnGetFileId<E>,
+    stage: Stage,
+    hash_options: HashOptions,
+    stats: &ScanStats,
 ) {
     /*
     if !file_path.is_file() {
@@ -32,62 +46,49 @@ fn enter_file<E: Display>(
     }
     */
 
-    CF.fetch_add(1, Ordering::Relaxed);
-
     // println!("file {}", file_path.to_string_lossy());
-    match get_file_id(&file_path) {
-        Ok(file_id) => {
-            let vec_opt = known_names.entry(file_id).or_default();
-            vec_opt.push(file_path);
-        }
-        Err(err) => eprintln!(
-            "Error when getting file identifier for `{}` : {}",
-            file_path.to_string_lossy(),
-            err
-        ),
+    match get_file_id(stage, hash_options, &file_path, stats) {
+        Ok(file_id) => known_names.push((file_id, file_path)),
+        Err(err) => stats.push_error(err),
     }
 }
 
-fn enter_dir<E: Display>(
-    known_names: &mut HashMap<String, Vec<PathBuf>>,
-    dir_path: PathBuf,
-    get_file_id: &FnGetFileId<E>,
-) {
+fn enter_dir(files: &mut Vec<FoundFile>, dir: Dir, options: &ScanOptions, stats: &ScanStats) {
     /*
     if !dir_path.is_dir() {
-        panic!("Not a directory : `{}`!", dir_path.to_string_lossy());
+        panic!("Not a directory : `{}`!", dir.path.to_string_lossy());
     }
     */
 
-    CD.fetch_add(1, Ordering::Relaxed);
+    stats.add_dir();
 
-    // println!("dir  {}", dir_path.to_string_lossy());
-    match read_dir(&dir_path) {
+    // println!("dir  {}", dir.path.to_string_lossy());
+    match read_dir(&dir.path) {
         Ok(entries) => entries.for_each(|entry_res| match entry_res {
             Ok(entry) => match entry.metadata() {
-                Ok(metadata) => {
-                    if metadata.is_dir() {
-                        enter_dir(known_names, entry.path(), get_file_id);
-                    } else if metadata.is_file() {
-                        enter_file(known_names, entry.path(), get_file_id);
+                Ok(metadata) => match classify(&dir, entry.path(), metadata, options, stats) {
+                    Ok(Entry::Dir(sub_dir)) => {
+                        enter_dir(files, sub_dir, options, stats);
+                    }
+                    Ok(Entry::File(file)) => {
+                        stats.add_file();
+                        files.push(file);
                     }
-                }
-                Err(err) => eprintln!(
-                    "Error when reading entry metadata `{}` : {}",
-                    entry.path().to_string_lossy(),
-                    err
-                ),
+                    Ok(Entry::Skipped) => stats.add_skipped(),
+                    Err(err) => stats.push_error(err),
+                },
+                Err(err) => stats.push_error(FindDoublesError::io(
+                    &entry.path(),
+                    Operation::ReadMetadata,
+                    err,
+                )),
             },
-            Err(err) => eprintln!(
-                "Error when reading dir entry `{}` : {}",
-                dir_path.to_string_lossy(),
-                err
-            ),
+            Err(err) => stats.push_error(FindDoublesError::io(
+                &dir.path,
+                Operation::ReadDirEntry,
+                err,
+            )),
         }),
-        Err(err) => eprintln!(
-            "Error when reading dir `{}` : {}",
-            dir_path.to_string_lossy(),
-            err
-        ),
+        Err(err) => stats.push_error(FindDoublesError::io(&dir.path, Operation::ReadDir, err)),
     }
 }
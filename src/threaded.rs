@@ -1,36 +1,56 @@
-use std::collections::HashMap;
 use std::fs::read_dir;
 use std::path::PathBuf;
-use std::sync::atomic::Ordering;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::Instant;
 
+use super::walk::{classify, list_files, Dir, Entry, FoundFile};
 use super::{
-    get_file_id_by_both, get_file_id_by_file_name, get_file_id_by_hash, Comparison, CD, CF,
+    find_doubles_by_stages, get_file_id, DuplicateGroup, FileKey, FindDoublesError, HardLinkGroup,
+    HashOptions, Operation, Phase, ScanOptions, ScanStats, Stage,
 };
 
-pub fn find_doubles(comp: Comparison, dir: PathBuf) -> HashMap<String, Vec<PathBuf>> {
+pub fn find_doubles(
+    options: &Arc<ScanOptions>,
+    roots: Vec<Dir>,
+    listed: Vec<PathBuf>,
+    stats: &Arc<ScanStats>,
+) -> (Vec<DuplicateGroup>, Vec<HardLinkGroup>) {
+    let hash_options = options.hash_options;
+
     thread::scope(move |s| {
         let (tx, rx) = mpsc::channel();
 
         let handle = s.spawn(move || {
-            let mut files = HashMap::new();
-            for p in rx {
-                enter_file(&mut files, p, comp);
-            }
-            files
+            find_doubles_by_stages(options, rx, stats, |stage, files| {
+                let mut known_names = Vec::with_capacity(files.len());
+                for p in files {
+                    enter_file(&mut known_names, p, stage, hash_options, stats);
+                }
+                known_names
+            })
         });
 
-        enter_dir(tx, dir);
+        let start = Instant::now();
+        for file in list_files(listed, options, stats) {
+            tx.send(file).unwrap();
+        }
+        for dir in roots {
+            enter_dir(tx.clone(), dir, options, stats);
+        }
+        drop(tx);
+        stats.add_timing(Phase::Walk, start.elapsed());
 
         handle.join().unwrap()
     })
 }
 
 fn enter_file(
-    known_names: &mut HashMap<String, Vec<PathBuf>>,
+    known_names: &mut Vec<(FileKey, PathBuf)>,
     file_path: PathBuf,
-    comp: Comparison,
+    stage: Stage,
+    hash_options: HashOptions,
+    stats: &ScanStats,
 ) {
     /*
     if !file_path.is_file() {
@@ -38,64 +58,54 @@ fn enter_file(
     }
     */
 
-    CF.fetch_add(1, Ordering::Relaxed);
-
     // println!("file {}", file_path.to_string_lossy());
-    let file_id = match comp {
-        Comparison::FileName => get_file_id_by_file_name(&file_path),
-        Comparison::Hash => get_file_id_by_hash(&file_path),
-        Comparison::Both => get_file_id_by_both(&file_path),
-    };
-
-    match file_id {
-        Ok(file_id) => {
-            let vec_opt = known_names.entry(file_id).or_default();
-            vec_opt.push(file_path);
-        }
-        Err(err) => eprintln!(
-            "Error when getting file identifier for `{}` : {}",
-            file_path.to_string_lossy(),
-            err
-        ),
+    match get_file_id(stage, hash_options, &file_path, stats) {
+        Ok(file_id) => known_names.push((file_id, file_path)),
+        Err(err) => stats.push_error(err),
     }
 }
 
-fn enter_dir(known_names: mpsc::Sender<PathBuf>, dir_path: PathBuf) {
+fn enter_dir(
+    known_names: mpsc::Sender<FoundFile>,
+    dir: Dir,
+    options: &ScanOptions,
+    stats: &ScanStats,
+) {
     /*
     if !dir_path.is_dir() {
-        panic!("Not a directory : `{}`!", dir_path.to_string_lossy());
+        panic!("Not a directory : `{}`!", dir.path.to_string_lossy());
     }
     */
 
-    CD.fetch_add(1, Ordering::Relaxed);
+    stats.add_dir();
 
-    // println!("dir  {}", dir_path.to_string_lossy());
-    match read_dir(&dir_path) {
+    // println!("dir  {}", dir.path.to_string_lossy());
+    match read_dir(&dir.path) {
         Ok(entries) => entries.for_each(|entry_res| match entry_res {
             Ok(entry) => match entry.metadata() {
-                Ok(metadata) => {
-                    if metadata.is_dir() {
-                        enter_dir(known_names.clone(), entry.path());
-                    } else if metadata.is_file() {
-                        known_names.send(entry.path()).unwrap();
+                Ok(metadata) => match classify(&dir, entry.path(), metadata, options, stats) {
+                    Ok(Entry::Dir(sub_dir)) => {
+                        enter_dir(known_names.clone(), sub_dir, options, stats);
                     }
-                }
-                Err(err) => eprintln!(
-                    "Error when reading entry metadata `{}` : {}",
-                    entry.path().to_string_lossy(),
-                    err
-                ),
+                    Ok(Entry::File(file)) => {
+                        stats.add_file();
+                        known_names.send(file).unwrap();
+                    }
+                    Ok(Entry::Skipped) => stats.add_skipped(),
+                    Err(err) => stats.push_error(err),
+                },
+                Err(err) => stats.push_error(FindDoublesError::io(
+                    &entry.path(),
+                    Operation::ReadMetadata,
+                    err,
+                )),
             },
-            Err(err) => eprintln!(
-                "Error when reading dir entry `{}` : {}",
-                dir_path.to_string_lossy(),
-                err
-            ),
+            Err(err) => stats.push_error(FindDoublesError::io(
+                &dir.path,
+                Operation::ReadDirEntry,
+                err,
+            )),
         }),
-        Err(err) => eprintln!(
-            "Error when reading dir `{}` : {}",
-            dir_path.to_string_lossy(),
-            err
-        ),
+        Err(err) => stats.push_error(FindDoublesError::io(&dir.path, Operation::ReadDir, err)),
     }
 }
@@ -0,0 +1,194 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+use super::{DuplicateFile, DuplicateGroup, FindDoublesError, Mismatch, Operation, ScanStats};
+
+/// Compares byte by byte the content of the files of each group and splits the groups whose files
+/// don't all have the same content.
+///
+/// Such a mismatch means either a hash collision or a file modified during the scan, so it is
+/// returned on its own. Files that can't be read anymore are removed from their group.
+pub fn verify_doubles(
+    doubles: Vec<DuplicateGroup>,
+    buffer_size: usize,
+    stats: &ScanStats,
+) -> (Vec<DuplicateGroup>, Vec<Mismatch>) {
+    let mut verified = Vec::with_capacity(doubles.len());
+    let mut mismatches = Vec::new();
+
+    for group in doubles {
+        let subgroups = split_by_content(group.files, buffer_size, stats);
+
+        if subgroups.len() > 1 {
+            mismatches.push(Mismatch {
+                key: group.key.clone(),
+                groups: subgroups.clone(),
+            });
+        }
+
+        verified.extend(
+            subgroups
+                .into_iter()
+                .filter(|subgroup| subgroup.len() > 1)
+                .map(|subgroup| DuplicateGroup {
+                    key: group.key.clone(),
+                    size: group.size,
+                    files: subgroup,
+                }),
+        );
+    }
+
+    (verified, mismatches)
+}
+
+/// Splits files into groups of files with exactly the same content. A file that can't be read is
+/// removed, without taking the files it was compared to along.
+fn split_by_content(
+    mut files: Vec<DuplicateFile>,
+    buffer_size: usize,
+    stats: &ScanStats,
+) -> Vec<Vec<DuplicateFile>> {
+    let mut subgroups = Vec::new();
+
+    while !files.is_empty() {
+        let reference = files.remove(0);
+        let mut subgroup = vec![];
+        let mut others = Vec::new();
+        let mut unreadable_reference = false;
+
+        for file in files {
+            if unreadable_reference {
+                others.push(file);
+                continue;
+            }
+
+            match same_content(&reference.path, &file.path, buffer_size, stats) {
+                Ok(true) => subgroup.push(file),
+                Ok(false) => others.push(file),
+                // Every comparison with the reference would fail, so only the reference is left
+                // out and the other files are compared among themselves.
+                Err(err) if err.path() == reference.path => {
+                    stats.push_error(err);
+                    unreadable_reference = true;
+                    others.push(file);
+                }
+                Err(err) => stats.push_error(err),
+            }
+        }
+
+        if unreadable_reference {
+            subgroup.append(&mut others);
+            files = subgroup;
+        } else {
+            subgroup.insert(0, reference);
+            subgroups.push(subgroup);
+            files = others;
+        }
+    }
+
+    subgroups
+}
+
+pub(crate) fn same_content(
+    file_a: &Path,
+    file_b: &Path,
+    buffer_size: usize,
+    stats: &ScanStats,
+) -> Result<bool, FindDoublesError> {
+    let (mut content_a, len_a) = open_with_len(file_a)?;
+    let (mut content_b, len_b) = open_with_len(file_b)?;
+
+    if len_a != len_b {
+        return Ok(false);
+    }
+
+    let mut buffer_a = vec![0; buffer_size.max(1)];
+    let mut buffer_b = vec![0; buffer_size.max(1)];
+
+    loop {
+        let len_a = fill_buffer(&mut content_a, &mut buffer_a)
+            .map_err(|err| FindDoublesError::io(file_a, Operation::Read, err))?;
+        let len_b = fill_buffer(&mut content_b, &mut buffer_b)
+            .map_err(|err| FindDoublesError::io(file_b, Operation::Read, err))?;
+        stats.add_bytes_read(len_a + len_b);
+
+        if buffer_a[..len_a] != buffer_b[..len_b] {
+            return Ok(false);
+        } else if len_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+fn open_with_len(path: &Path) -> Result<(File, u64), FindDoublesError> {
+    let file = File::open(path).map_err(|err| FindDoublesError::io(path, Operation::Open, err))?;
+    let len = file
+        .metadata()
+        .map_err(|err| FindDoublesError::io(path, Operation::ReadMetadata, err))?
+        .len();
+    Ok((file, len))
+}
+
+/// Reads until `buffer` is full or the end of `content` is reached, so two files can be compared
+/// one buffer at a time.
+fn fill_buffer(content: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+
+    while len < buffer.len() {
+        match content.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn file(path: std::path::PathBuf) -> DuplicateFile {
+        DuplicateFile {
+            path,
+            root: 0,
+            reference: false,
+            symlink: false,
+        }
+    }
+
+    #[test]
+    fn unreadable_first_file_only_drops_itself() {
+        let dir = TestDir::new();
+        let missing = file(dir.path().join("missing"));
+        let a = file(dir.write("a", "same"));
+        let b = file(dir.write("b", "same"));
+        let c = file(dir.write("c", "diff"));
+        let stats = ScanStats::default();
+
+        let subgroups = split_by_content(vec![missing, a.clone(), b.clone(), c.clone()], 2, &stats);
+
+        assert_eq!(subgroups, vec![vec![a, b], vec![c]]);
+        assert_eq!(stats.errors(), 1);
+    }
+
+    #[test]
+    fn splits_different_contents() {
+        let dir = TestDir::new();
+        let a = file(dir.write("a", "one"));
+        let b = file(dir.write("b", "two"));
+        let c = file(dir.write("c", "one"));
+
+        let subgroups = split_by_content(
+            vec![a.clone(), b.clone(), c.clone()],
+            2,
+            &ScanStats::default(),
+        );
+
+        assert_eq!(subgroups, vec![vec![a, c], vec![b]]);
+    }
+}
@@ -0,0 +1,481 @@
+extern crate ignore;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use super::{FindDoublesError, Operation, ScanOptions, ScanStats, SymlinkPolicy};
+
+/// Files listing paths to ignore, read in each directory with
+/// [`ScanOptions::ignore_files`], from the lowest to the highest precedence.
+const IGNORE_FILES: [&str; 3] = [".gitignore", ".ignore", ".find_doubles_ignore"];
+
+/// Directory to walk through, with the identifiers of the directories leading to it.
+#[derive(Clone, Debug)]
+pub struct Dir {
+    pub path: PathBuf,
+    /// Index of the root the walk started from, in the roots given to [`roots`].
+    root: usize,
+    /// Whether the root is a reference directory.
+    reference: bool,
+    /// (device, inode) of every root, so a root nested in another one is only walked once.
+    roots: Arc<Vec<(u64, u64)>>,
+    /// (device, inode) of this directory and of its parents, to detect symlink loops.
+    ancestors: Vec<(u64, u64)>,
+    /// Device of the root the walk started from.
+    root_device: Option<u64>,
+    /// Ignore rules of this directory and of its parents, from the root.
+    ignores: Vec<Arc<Gitignore>>,
+    /// Number of directories between the root and this one, `0` for the root.
+    depth: usize,
+}
+
+/// Directories to start the walks from, one for each of `paths` except those that are the same
+/// directory as a previous one. The paths from index `first_reference` are reference directories.
+///
+/// Files are tagged with the index of their root in `paths`. The walk of a root doesn't enter the
+/// other roots nested in it, so their files are only found once, tagged with the closest root. A
+/// nested root still follows the ignore files of the roots around it (see [`inherited_ignores`]),
+/// while its depth starts again from `0`. An empty path stands for a list of files (see
+/// [`list_files`]) and isn't walked.
+pub fn roots(
+    paths: &[PathBuf],
+    first_reference: usize,
+    options: &ScanOptions,
+    stats: &ScanStats,
+) -> Vec<Dir> {
+    let ids: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            fs::metadata(path)
+                .ok()
+                .and_then(|metadata| file_id(&metadata))
+        })
+        .collect();
+    let roots = Arc::new(ids.iter().flatten().copied().collect::<Vec<_>>());
+    let canonical: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            (options.ignore_files && !path.as_os_str().is_empty())
+                .then(|| fs::canonicalize(path).ok())
+                .flatten()
+        })
+        .collect();
+
+    let mut dirs: Vec<Dir> = Vec::with_capacity(paths.len());
+    for (root, (path, id)) in paths.iter().zip(ids).enumerate() {
+        if path.as_os_str().is_empty()
+            || (id.is_some() && dirs.iter().any(|dir| dir.ancestors.first() == id.as_ref()))
+        {
+            continue;
+        }
+        let mut ignores = inherited_ignores(path, root, &canonical);
+        if options.ignore_files {
+            ignores.extend(read_ignore_files(path, stats));
+        }
+        dirs.push(Dir::root(
+            path.clone(),
+            root,
+            root >= first_reference,
+            id,
+            &roots,
+            ignores,
+        ));
+    }
+
+    dirs
+}
+
+impl Dir {
+    fn root(
+        path: PathBuf,
+        root: usize,
+        reference: bool,
+        root_id: Option<(u64, u64)>,
+        roots: &Arc<Vec<(u64, u64)>>,
+        ignores: Vec<Arc<Gitignore>>,
+    ) -> Self {
+        Dir {
+            path,
+            root,
+            reference,
+            roots: roots.clone(),
+            ancestors: root_id.into_iter().collect(),
+            root_device: root_id.map(|(device, _)| device),
+            ignores,
+            depth: 0,
+        }
+    }
+
+    fn child(
+        &self,
+        path: PathBuf,
+        metadata: &Metadata,
+        options: &ScanOptions,
+        stats: &ScanStats,
+    ) -> Self {
+        let mut ancestors = self.ancestors.clone();
+        ancestors.extend(file_id(metadata));
+        let mut ignores = self.ignores.clone();
+        if options.ignore_files {
+            ignores.extend(read_ignore_files(&path, stats));
+        }
+
+        Dir {
+            path,
+            root: self.root,
+            reference: self.reference,
+            roots: self.roots.clone(),
+            ancestors,
+            root_device: self.root_device,
+            ignores,
+            depth: self.depth + 1,
+        }
+    }
+
+    /// Whether `path` is ignored by the rules of the closest directory having a rule about it.
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for ignore in self.ignores.iter().rev() {
+            match ignore.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => (),
+            }
+        }
+
+        false
+    }
+
+    fn is_other_device(&self, metadata: &Metadata) -> bool {
+        match (self.root_device, file_id(metadata)) {
+            (Some(root_device), Some((device, _))) => device != root_device,
+            _ => false,
+        }
+    }
+
+    fn contains_loop(&self, metadata: &Metadata) -> bool {
+        file_id(metadata).is_some_and(|id| self.ancestors.contains(&id))
+    }
+
+    /// Whether the directory is one of the roots, walked on its own.
+    fn is_root(&self, metadata: &Metadata) -> bool {
+        file_id(metadata).is_some_and(|id| self.roots.contains(&id))
+    }
+}
+
+/// Regular file found by a walker.
+#[derive(Clone, Debug)]
+pub struct FoundFile {
+    pub path: PathBuf,
+    pub size: u64,
+    /// (device, inode) of the file, shared by all its hard links. It is `None` for a symlink, which
+    /// is a path of its own rather than a hard link to its target.
+    pub id: Option<(u64, u64)>,
+    /// Index of the root the file was found in.
+    pub root: usize,
+    /// Whether the root is a reference directory.
+    pub reference: bool,
+    /// Whether the path is a symlink, followed or reported.
+    pub symlink: bool,
+}
+
+/// What a walker does with an entry of a directory.
+pub enum Entry {
+    Dir(Dir),
+    File(FoundFile),
+    Skipped,
+}
+
+/// Decides what to do with the entry at `path` of `dir`, given its own metadata (not following
+/// symlinks), so every backend walks the same way.
+///
+/// Entries are filtered by [`ScanOptions::include_hidden`], [`ScanOptions::filter`], the include
+/// and exclude patterns, the ignore files and, for files, their depth and size. Directories are
+/// only entered up to [`ScanOptions::max_depth`], and not when they are one of the roots, as they
+/// are walked on their own. Symlinks are handled according to [`ScanOptions::symlinks`], their
+/// target is read with a blocking call, even in the async backends, as they are expected to be
+/// rare. With [`ScanOptions::one_file_system`], the directories on another device than the root
+/// are recorded in `stats` as mount points instead of being entered.
+pub fn classify(
+    dir: &Dir,
+    path: PathBuf,
+    metadata: Metadata,
+    options: &ScanOptions,
+    stats: &ScanStats,
+) -> Result<Entry, FindDoublesError> {
+    if (is_hidden(&path) && !options.include_hidden) || !options.accepts(&path) {
+        return Ok(Entry::Skipped);
+    }
+    // Depth of the entry, counting the root's entries as `1`.
+    let depth = dir.depth + 1;
+
+    let is_symlink = metadata.is_symlink();
+    let metadata = if is_symlink {
+        if options.symlinks == SymlinkPolicy::Ignore {
+            return Ok(Entry::Skipped);
+        }
+
+        match fs::metadata(&path) {
+            Ok(target) if target.is_dir() && options.symlinks == SymlinkPolicy::Report => {
+                return Ok(Entry::Skipped);
+            }
+            Ok(target) => target,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(FindDoublesError::BrokenSymlink { path });
+            }
+            Err(err) => return Err(FindDoublesError::io(&path, Operation::ReadMetadata, err)),
+        }
+    } else {
+        metadata
+    };
+
+    if dir.is_ignored(&path, metadata.is_dir()) {
+        return Ok(Entry::Skipped);
+    }
+
+    if metadata.is_dir() {
+        if options
+            .max_depth
+            .is_some_and(|max_depth| depth >= max_depth)
+            || !options.filters.accepts_dir(&path)
+            || dir.contains_loop(&metadata)
+            || dir.is_root(&metadata)
+        {
+            Ok(Entry::Skipped)
+        } else if options.one_file_system && dir.is_other_device(&metadata) {
+            stats.add_mount_point(path);
+            Ok(Entry::Skipped)
+        } else {
+            Ok(Entry::Dir(dir.child(path, &metadata, options, stats)))
+        }
+    } else if metadata.is_file()
+        && depth >= options.min_depth
+        && options.max_depth.is_none_or(|max_depth| depth <= max_depth)
+        && options.accepts_size(metadata.len())
+        && options.filters.accepts_file(&path)
+    {
+        Ok(Entry::File(FoundFile {
+            path,
+            size: metadata.len(),
+            id: if is_symlink { None } else { file_id(&metadata) },
+            root: dir.root,
+            reference: dir.reference,
+            symlink: is_symlink,
+        }))
+    } else {
+        Ok(Entry::Skipped)
+    }
+}
+
+/// Reads the metadata of each listed path, counting them in `stats`, to be compared like the files
+/// found by walking the first root.
+///
+/// Symlinks are followed as the paths were explicitly given, and paths given twice are only kept
+/// once. The listed files are filtered like the walked ones, except by their depth and the ignore
+/// files, while the paths that aren't regular files are skipped.
+pub fn list_files(paths: Vec<PathBuf>, options: &ScanOptions, stats: &ScanStats) -> Vec<FoundFile> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+
+        if (is_hidden(&path) && !options.include_hidden) || !options.accepts(&path) {
+            stats.add_skipped();
+            continue;
+        }
+
+        match fs::metadata(&path) {
+            Ok(metadata)
+                if metadata.is_file()
+                    && options.accepts_size(metadata.len())
+                    && options.filters.accepts_file(&path) =>
+            {
+                stats.add_file();
+                let is_symlink = fs::symlink_metadata(&path).is_ok_and(|link| link.is_symlink());
+                files.push(FoundFile {
+                    size: metadata.len(),
+                    id: if is_symlink { None } else { file_id(&metadata) },
+                    path,
+                    root: 0,
+                    reference: false,
+                    symlink: is_symlink,
+                });
+            }
+            Ok(_) => stats.add_skipped(),
+            Err(err) => stats.push_error(FindDoublesError::io(&path, Operation::ReadMetadata, err)),
+        }
+    }
+
+    files
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+}
+
+/// Ignore rules of the directories from the outermost root containing the root at `index` (given
+/// their `canonical` paths) down to its parent, so a nested root skips the same files as the walk
+/// of the outer root would. The directories are reached from `path`, the way the nested root is
+/// walked, so the rules match its paths : a nested root given through a symlink or `..` only
+/// follows its own ignore files. Errors are left to the walk of the outer root, which reads the
+/// same files.
+fn inherited_ignores(
+    path: &Path,
+    index: usize,
+    canonical: &[Option<PathBuf>],
+) -> Vec<Arc<Gitignore>> {
+    let Some(nested) = &canonical[index] else {
+        return Vec::new();
+    };
+    let depth = canonical
+        .iter()
+        .flatten()
+        .filter_map(|outer| nested.strip_prefix(outer).ok())
+        .map(|relative| relative.components().count())
+        .max()
+        .unwrap_or_default();
+
+    let mut dirs = Vec::with_capacity(depth);
+    let mut dir = path;
+    for _ in 0..depth {
+        match (dir.components().next_back(), dir.parent()) {
+            (Some(Component::Normal(_)), Some(parent)) => dir = parent,
+            _ => return Vec::new(),
+        }
+        dirs.push(dir);
+    }
+
+    let stats = ScanStats::default();
+    dirs.iter()
+        .rev()
+        .filter_map(|dir| read_ignore_files(dir, &stats))
+        .collect()
+}
+
+/// Reads the ignore files of `dir`, if it has any.
+fn read_ignore_files(dir: &Path, stats: &ScanStats) -> Option<Arc<Gitignore>> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+    for name in IGNORE_FILES {
+        let ignore_file = dir.join(name);
+        if ignore_file.is_file() {
+            found = true;
+            if let Some(err) = builder.add(&ignore_file) {
+                stats.push_error(FindDoublesError::IgnoreFile {
+                    path: ignore_file,
+                    message: err.to_string(),
+                });
+            }
+        }
+    }
+
+    if !found {
+        return None;
+    }
+
+    match builder.build() {
+        Ok(ignore) => Some(Arc::new(ignore)),
+        Err(err) => {
+            stats.push_error(FindDoublesError::IgnoreFile {
+                path: dir.to_owned(),
+                message: err.to_string(),
+            });
+            None
+        }
+    }
+}
+
+/// (device, inode) of a file, shared by all its hard links.
+#[cfg(unix)]
+pub fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::test_dir::TestDir;
+    use crate::{find_doubles, Comparison, ScanOptions};
+
+    #[test]
+    fn max_depth_limits_files() {
+        let dir = TestDir::new();
+        dir.write("a", "hello");
+        dir.write("sub/a", "hello");
+        dir.write("sub/deeper/a", "hello");
+
+        let scanned = |max_depth| {
+            let options = ScanOptions::new().max_depth(max_depth);
+            let report = find_doubles(&[dir.path().to_owned()], &options);
+            report.stats.files()
+        };
+
+        assert_eq!(scanned(0), 0);
+        assert_eq!(scanned(1), 1);
+        assert_eq!(scanned(2), 2);
+        assert_eq!(scanned(3), 3);
+    }
+
+    #[test]
+    fn nested_roots_are_walked_once() {
+        let dir = TestDir::new();
+        let a = dir.write("r/a", "hello");
+        let b = dir.write("r/sub/b", "hello");
+        let options = ScanOptions::new().comparison(Comparison::Hash);
+
+        for roots in [["r", "r/sub"], ["r/sub", "r"]] {
+            let roots = roots.map(|root| dir.path().join(root));
+            let report = find_doubles(&roots, &options);
+
+            assert_eq!(report.stats.files(), 2);
+            assert_eq!(report.groups.len(), 1);
+            let tagged = |path: &PathBuf| {
+                let file = report.groups[0]
+                    .files
+                    .iter()
+                    .find(|file| &file.path == path);
+                roots[file.unwrap().root].clone()
+            };
+            assert_eq!(tagged(&a), dir.path().join("r"));
+            assert_eq!(tagged(&b), dir.path().join("r/sub"));
+        }
+    }
+
+    #[test]
+    fn nested_roots_follow_the_outer_ignore_files() {
+        let dir = TestDir::new();
+        dir.write("r/.gitignore", "sub/deep\n");
+        dir.write("r/a", "hello");
+        dir.write("r/sub/b", "hello");
+        dir.write("r/sub/deep/c", "hello");
+        let options = ScanOptions::new().ignore_files(true);
+
+        let scanned = |roots: &[&str]| {
+            let roots: Vec<_> = roots.iter().map(|root| dir.path().join(root)).collect();
+            find_doubles(&roots, &options).stats.files()
+        };
+
+        // The ignore file itself, `a` and `sub/b`.
+        assert_eq!(scanned(&["r"]), 3);
+        assert_eq!(scanned(&["r", "r/sub"]), 3);
+        assert_eq!(scanned(&["r/sub", "r"]), 3);
+        // Without the outer root, its ignore files aren't read.
+        assert_eq!(scanned(&["r/sub"]), 2);
+    }
+}